@@ -1,6 +1,6 @@
 use anchor_lang::prelude::*;
 
-declare_id!("11111111111111111111111111111111");
+declare_id!("HuvfZBXs4mP3RnJQxcDPL2nbV52dn51S5yQEKaD833op");
 
 #[program]
 pub mod anft_did {
@@ -33,11 +33,13 @@ pub mod anft_did {
         profile.created_at = clock.unix_timestamp;
         profile.attestation_count = 0;
         profile.bump = ctx.bumps.did_profile;
+        profile.version = DidProfile::VERSION;
 
         // Set WalletLookup fields
         lookup.wallet = ctx.accounts.signer.key();
         lookup.pda_address = profile.key();
         lookup.bump = ctx.bumps.wallet_lookup;
+        lookup.version = WalletLookup::VERSION;
 
         Ok(())
     }
@@ -56,6 +58,7 @@ pub mod anft_did {
         new_lookup.wallet = new_wallet;
         new_lookup.pda_address = profile.key();
         new_lookup.bump = ctx.bumps.new_wallet_lookup;
+        new_lookup.version = WalletLookup::VERSION;
 
         // old_wallet_lookup is closed via close = signer constraint
 
@@ -72,13 +75,117 @@ pub mod anft_did {
             .ok_or(AnftError::Overflow)?;
         Ok(())
     }
+
+    /// Realloc a DidProfile created before accounts carried a version byte.
+    /// Permissionless; the payer funds the extra rent. Until then the profile
+    /// is read and written in its legacy layout.
+    pub fn migrate_did_profile(ctx: Context<MigrateDidProfile>) -> Result<()> {
+        let profile = &mut ctx.accounts.did_profile;
+        profile.version = profile.version.max(DidProfile::VERSION);
+        Ok(())
+    }
+
+    /// Realloc a WalletLookup created before accounts carried a version byte.
+    pub fn migrate_wallet_lookup(ctx: Context<MigrateWalletLookup>) -> Result<()> {
+        let lookup = &mut ctx.accounts.wallet_lookup;
+        lookup.version = lookup.version.max(WalletLookup::VERSION);
+        Ok(())
+    }
 }
 
 // ═══════════════════════════════════════════════════
 // ACCOUNTS
 // ═══════════════════════════════════════════════════
 
-#[account]
+/// Implements the Anchor account traits for a versioned account. Data is read as
+/// the current layout when a non-zero version byte follows the `legacy` prefix,
+/// otherwise as the legacy layout; version 0 accounts are written back in the
+/// legacy layout so they keep fitting their allocation until migrated.
+macro_rules! versioned_account {
+    ($name:ident { $($field:ident),* }) => {
+        impl anchor_lang::Discriminator for $name {
+            const DISCRIMINATOR: [u8; 8] =
+                <legacy::$name as anchor_lang::Discriminator>::DISCRIMINATOR;
+        }
+
+        impl anchor_lang::Owner for $name {
+            fn owner() -> Pubkey {
+                crate::ID
+            }
+        }
+
+        impl anchor_lang::AccountSerialize for $name {
+            fn try_serialize<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+                writer
+                    .write_all(&<Self as anchor_lang::Discriminator>::DISCRIMINATOR)
+                    .map_err(|_| ErrorCode::AccountDidNotSerialize)?;
+                let written = if self.version == 0 {
+                    let legacy = legacy::$name { $($field: self.$field.clone()),* };
+                    AnchorSerialize::serialize(&legacy, writer)
+                } else {
+                    AnchorSerialize::serialize(self, writer)
+                };
+                written.map_err(|_| ErrorCode::AccountDidNotSerialize.into())
+            }
+        }
+
+        impl anchor_lang::AccountDeserialize for $name {
+            fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+                let discriminator = <Self as anchor_lang::Discriminator>::DISCRIMINATOR;
+                if buf.len() < discriminator.len() {
+                    return err!(ErrorCode::AccountDiscriminatorNotFound);
+                }
+                if buf[..8] != discriminator {
+                    return Err(error!(ErrorCode::AccountDiscriminatorMismatch)
+                        .with_account_name(stringify!($name)));
+                }
+                Self::try_deserialize_unchecked(buf)
+            }
+
+            fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+                let mut data: &[u8] = &buf[8..];
+                let mut rest = data;
+                let parsed = match <legacy::$name as AnchorDeserialize>::deserialize(&mut rest) {
+                    // Legacy accounts end here, or are zero-padded up to their MAX_SIZE
+                    Ok(legacy) if matches!(rest.first(), None | Some(0)) => Ok($name {
+                        $($field: legacy.$field,)*
+                        version: 0,
+                        reserved: std::array::from_fn(|_| 0),
+                    }),
+                    _ => AnchorDeserialize::deserialize(&mut data),
+                };
+                parsed.map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+            }
+        }
+    };
+}
+
+/// Layouts of accounts created before they carried a version byte. Each shares
+/// its discriminator with the current type, whose layout starts with these fields.
+pub mod legacy {
+    use super::*;
+
+    #[account]
+    pub struct DidProfile {
+        pub pda_address: Pubkey,
+        pub username: String,
+        pub did: String,
+        pub current_wallet: Pubkey,
+        pub original_wallet: Pubkey,
+        pub created_at: i64,
+        pub attestation_count: u64,
+        pub bump: u8,
+    }
+
+    #[account]
+    pub struct WalletLookup {
+        pub wallet: Pubkey,
+        pub pda_address: Pubkey,
+        pub bump: u8,
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct DidProfile {
     /// This account's own public key – the canonical DID identifier.
     pub pda_address: Pubkey,        // 32
@@ -96,15 +203,25 @@ pub struct DidProfile {
     pub attestation_count: u64,     // 8
     /// PDA bump seed.
     pub bump: u8,                   // 1
+    /// Layout version; 0 for accounts still in the legacy layout.
+    pub version: u8,                // 1
+    /// Zeroed space for fields added by later versions.
+    pub reserved: [u8; 64],         // 64
 }
 
+versioned_account!(DidProfile {
+    pda_address, username, did, current_wallet, original_wallet, created_at,
+    attestation_count, bump
+});
+
 impl DidProfile {
-    // 8 (discriminator) + 32 + 36 + 68 + 32 + 32 + 8 + 8 + 1 = 225
+    pub const VERSION: u8 = 1;
+    // 8 (discriminator) + 32 + 36 + 68 + 32 + 32 + 8 + 8 + 1 + 1 + 64 = 290
     // Add generous padding for string length variance
-    pub const MAX_SIZE: usize = 8 + 32 + (4 + 32) + (4 + 64) + 32 + 32 + 8 + 8 + 1;
+    pub const MAX_SIZE: usize = 8 + 32 + (4 + 32) + (4 + 64) + 32 + 32 + 8 + 8 + 1 + 1 + 64;
 }
 
-#[account]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct WalletLookup {
     /// The wallet public key.
     pub wallet: Pubkey,       // 32
@@ -112,11 +229,18 @@ pub struct WalletLookup {
     pub pda_address: Pubkey,  // 32
     /// PDA bump seed.
     pub bump: u8,             // 1
+    /// Layout version; 0 for accounts still in the legacy layout.
+    pub version: u8,          // 1
+    /// Zeroed space for fields added by later versions.
+    pub reserved: [u8; 32],   // 32
 }
 
+versioned_account!(WalletLookup { wallet, pda_address, bump });
+
 impl WalletLookup {
-    // 8 (discriminator) + 32 + 32 + 1 = 73
-    pub const MAX_SIZE: usize = 8 + 32 + 32 + 1;
+    pub const VERSION: u8 = 1;
+    // 8 (discriminator) + 32 + 32 + 1 + 1 + 32 = 106
+    pub const MAX_SIZE: usize = 8 + 32 + 32 + 1 + 1 + 32;
 }
 
 // ═══════════════════════════════════════════════════
@@ -193,6 +317,42 @@ pub struct IncrementAttestation<'info> {
     pub did_profile: Account<'info, DidProfile>,
 }
 
+#[derive(Accounts)]
+pub struct MigrateDidProfile<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"did", did_profile.username.as_bytes()],
+        bump = did_profile.bump,
+        realloc = DidProfile::MAX_SIZE,
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub did_profile: Account<'info, DidProfile>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateWalletLookup<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"wallet-did", wallet_lookup.wallet.as_ref()],
+        bump = wallet_lookup.bump,
+        realloc = WalletLookup::MAX_SIZE,
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub wallet_lookup: Account<'info, WalletLookup>,
+
+    pub system_program: Program<'info, System>,
+}
+
 // ═══════════════════════════════════════════════════
 // ERRORS
 // ═══════════════════════════════════════════════════
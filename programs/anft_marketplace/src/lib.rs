@@ -1,6 +1,10 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::program_option::COption;
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_spl::token::{self, Approve, CloseAccount, Mint, Revoke, Token, TokenAccount, Transfer};
+use anft_did::DidProfile;
 
 declare_id!("8fpA4QsK2kwNd9JxqXd2S23FsspmFiKStmKYNBzGE8bK");
 
@@ -8,24 +12,36 @@ declare_id!("8fpA4QsK2kwNd9JxqXd2S23FsspmFiKStmKYNBzGE8bK");
 pub mod anft_marketplace {
     use super::*;
 
+    /// Create the default marketplace instance.
     pub fn initialize_marketplace(ctx: Context<InitializeMarketplace>, fee_bps: u16) -> Result<()> {
-        require!(fee_bps <= 1000, MarketplaceError::FeeTooHigh);
-
-        let marketplace = &mut ctx.accounts.marketplace;
-        marketplace.admin = ctx.accounts.admin.key();
-        marketplace.fee_recipient = ctx.accounts.fee_recipient.key();
-        marketplace.fee_bps = fee_bps;
-        marketplace.paused = false;
-        marketplace.listing_count = 0;
-        marketplace.bump = ctx.bumps.marketplace;
-
-        emit!(MarketplaceInitialized {
-            admin: marketplace.admin,
-            fee_recipient: marketplace.fee_recipient,
+        ctx.accounts.marketplace.init(
+            Pubkey::default(),
+            ctx.accounts.admin.key(),
+            ctx.accounts.fee_recipient.key(),
             fee_bps,
-        });
+            ctx.bumps.marketplace,
+        )
+    }
 
-        Ok(())
+    /// Create a partner storefront with its own admin, fees and pause state. Its
+    /// listings, offers, escrows and config accounts are seeded with `instance`. The
+    /// default instance's admin must approve it.
+    pub fn initialize_instance(
+        ctx: Context<InitializeInstance>,
+        instance: Pubkey,
+        fee_bps: u16,
+    ) -> Result<()> {
+        require!(
+            instance != Pubkey::default(),
+            MarketplaceError::InvalidInstance
+        );
+        ctx.accounts.marketplace.init(
+            instance,
+            ctx.accounts.admin.key(),
+            ctx.accounts.fee_recipient.key(),
+            fee_bps,
+            ctx.bumps.marketplace,
+        )
     }
 
     pub fn list_nft(
@@ -33,17 +49,41 @@ pub mod anft_marketplace {
         price: u64,
         duration: i64,
         is_auction: bool,
+        auto_accept_price: Option<u64>,
+        start_time: Option<i64>,
+        hold_period: i64,
     ) -> Result<()> {
-        require!(
-            !ctx.accounts.marketplace.paused,
-            MarketplaceError::MarketplacePaused
-        );
-        require!(price > 0, MarketplaceError::PriceMustBePositive);
-        require!(duration >= 86400, MarketplaceError::DurationTooShort);
+        let terms = ListingTerms {
+            price,
+            duration,
+            is_auction,
+            auto_accept_price,
+            start_time,
+            hold_period,
+        };
+        ctx.accounts
+            .marketplace
+            .require_unpaused(terms.pause_flags())?;
+        let (verified_collection, collection) = CollectionMatch::for_listing(
+            ctx.accounts.collection_config.as_deref(),
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.nft_mint.key(),
+            ctx.accounts.marketplace.instance_seed(),
+        )?;
+        terms.validate(collection.as_ref().map(|collection| &collection.config))?;
+        BlocklistEntry::require_clear(&ctx.accounts.mint_blocklist, &ctx.accounts.nft_mint.key())?;
+        BlocklistEntry::require_clear(&ctx.accounts.seller_blocklist, &ctx.accounts.seller.key())?;
+        grow_legacy_account(
+            &ctx.accounts.listing.to_account_info(),
+            ctx.accounts.seller.as_ref(),
+            8 + Listing::INIT_SPACE,
+            ctx.accounts.system_program.as_ref(),
+        )?;
 
         let listing = &mut ctx.accounts.listing;
 
-        // If the listing PDA already exists from a previous sale, it must be inactive
+        // Sales and cancellations close the listing PDA, but one left open by an older sale
+        // can be reopened as long as it is inactive
         require!(!listing.is_active, MarketplaceError::ListingNotActive);
 
         // Verify the seller actually owns the NFT
@@ -54,19 +94,16 @@ pub mod anft_marketplace {
 
         let clock = Clock::get()?;
 
-        listing.seller = ctx.accounts.seller.key();
-        listing.nft_mint = ctx.accounts.nft_mint.key();
-        listing.price = price;
-        listing.expiration_time = clock
-            .unix_timestamp
-            .checked_add(duration)
-            .ok_or(MarketplaceError::Overflow)?;
-        listing.is_active = true;
-        listing.is_auction = is_auction;
-        listing.highest_bid = 0;
-        listing.highest_bidder = Pubkey::default();
-        listing.created_at = clock.unix_timestamp;
-        listing.bump = ctx.bumps.listing;
+        listing.open(
+            ctx.accounts.seller.key(),
+            ctx.accounts.nft_mint.key(),
+            &terms,
+            true,
+            ctx.bumps.listing,
+            clock.unix_timestamp,
+        )?;
+        listing.revision = ctx.accounts.marketplace.first_listing_revision()?;
+        listing.collection = verified_collection;
 
         let escrow = &mut ctx.accounts.escrow;
         escrow.nft_mint = ctx.accounts.nft_mint.key();
@@ -85,65 +122,221 @@ pub mod anft_marketplace {
             1,
         )?;
 
-        let marketplace = &mut ctx.accounts.marketplace;
-        marketplace.listing_count = marketplace
-            .listing_count
-            .checked_add(1)
-            .ok_or(MarketplaceError::Overflow)?;
+        ctx.accounts.marketplace.listings_opened(1)?;
+
+        emit!(ListingCreated {
+            seller: listing.seller,
+            nft_mint: listing.nft_mint,
+            price,
+            is_auction,
+            is_custodial: true,
+            start_time: listing.start_time,
+            hold_period: listing.hold_period,
+            expiration_time: listing.expiration_time,
+        });
+
+        Ok(())
+    }
+
+    /// List an NFT without moving it out of the seller's wallet. The escrow PDA is
+    /// approved as delegate and the token account is frozen through the mint's freeze
+    /// authority (the Metaplex edition), so it cannot move until settled or cancelled.
+    /// Only standard Metaplex NFTs are supported. Programmable NFTs need the token-metadata
+    /// lock flow, which this program does not implement; they are refused with
+    /// `ProgrammableNftUnsupported` in either listing mode.
+    pub fn list_nft_in_place(
+        ctx: Context<ListNftInPlace>,
+        price: u64,
+        duration: i64,
+        is_auction: bool,
+        auto_accept_price: Option<u64>,
+        start_time: Option<i64>,
+        hold_period: i64,
+    ) -> Result<()> {
+        let terms = ListingTerms {
+            price,
+            duration,
+            is_auction,
+            auto_accept_price,
+            start_time,
+            hold_period,
+        };
+        ctx.accounts
+            .marketplace
+            .require_unpaused(terms.pause_flags())?;
+        let (verified_collection, collection) = CollectionMatch::for_listing(
+            ctx.accounts.collection_config.as_deref(),
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.nft_mint.key(),
+            ctx.accounts.marketplace.instance_seed(),
+        )?;
+        terms.validate(collection.as_ref().map(|collection| &collection.config))?;
+        BlocklistEntry::require_clear(&ctx.accounts.mint_blocklist, &ctx.accounts.nft_mint.key())?;
+        BlocklistEntry::require_clear(&ctx.accounts.seller_blocklist, &ctx.accounts.seller.key())?;
+        grow_legacy_account(
+            &ctx.accounts.listing.to_account_info(),
+            ctx.accounts.seller.as_ref(),
+            8 + Listing::INIT_SPACE,
+            ctx.accounts.system_program.as_ref(),
+        )?;
+
+        let listing = &mut ctx.accounts.listing;
+        require!(!listing.is_active, MarketplaceError::ListingNotActive);
+
+        require!(
+            ctx.accounts.seller_token_account.amount == 1,
+            MarketplaceError::SellerDoesNotOwnNft
+        );
+        require!(
+            !ctx.accounts.seller_token_account.is_frozen(),
+            MarketplaceError::TokenAccountFrozen
+        );
+        require!(
+            ctx.accounts.nft_mint.freeze_authority == Some(ctx.accounts.nft_edition.key()).into(),
+            MarketplaceError::InvalidFreezeAuthority
+        );
+
+        let clock = Clock::get()?;
+
+        listing.open(
+            ctx.accounts.seller.key(),
+            ctx.accounts.nft_mint.key(),
+            &terms,
+            false,
+            ctx.bumps.listing,
+            clock.unix_timestamp,
+        )?;
+        listing.revision = ctx.accounts.marketplace.first_listing_revision()?;
+        listing.collection = verified_collection;
 
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.nft_mint = ctx.accounts.nft_mint.key();
+        escrow.bump = ctx.bumps.escrow;
+
+        // Approve the escrow PDA as delegate over the seller's token account
+        token::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Approve {
+                    to: ctx.accounts.seller_token_account.to_account_info(),
+                    delegate: ctx.accounts.escrow.to_account_info(),
+                    authority: ctx.accounts.seller.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        // Freeze the token account in place, signing as the delegate
+        let nft_mint_key = ctx.accounts.nft_mint.key();
+        let escrow_seeds: &[&[u8]] = &[
+            b"escrow",
+            ctx.accounts.marketplace.instance_seed(),
+            nft_mint_key.as_ref(),
+            &[ctx.accounts.escrow.bump],
+        ];
+        ctx.accounts.custody().freeze(escrow_seeds)?;
+
+        ctx.accounts.marketplace.listings_opened(1)?;
+
+        let listing = &ctx.accounts.listing;
         emit!(ListingCreated {
             seller: listing.seller,
             nft_mint: listing.nft_mint,
             price,
             is_auction,
+            is_custodial: false,
+            start_time: listing.start_time,
+            hold_period: listing.hold_period,
             expiration_time: listing.expiration_time,
         });
 
         Ok(())
     }
 
+    /// Cancel a listing as its seller or the listing moderator, handing back the NFT. A
+    /// moderator cancelling an in-place listing must pass the escrow token account, which
+    /// they pay for and get back: the NFT makes a round trip through it to clear the
+    /// escrow's delegation without the seller's signature.
     pub fn cancel_listing(ctx: Context<CancelListing>) -> Result<()> {
         let listing = &ctx.accounts.listing;
         let is_seller = ctx.accounts.authority.key() == listing.seller;
-        let is_admin = ctx.accounts.authority.key() == ctx.accounts.marketplace.admin;
-        require!(is_seller || is_admin, MarketplaceError::Unauthorized);
+        let is_moderator = ctx
+            .accounts
+            .marketplace
+            .holds_role(Role::ListingModerator, &ctx.accounts.authority.key());
+        require!(is_seller || is_moderator, MarketplaceError::Unauthorized);
         require!(listing.is_active, MarketplaceError::ListingNotActive);
 
         let nft_mint_key = ctx.accounts.nft_mint.key();
         let escrow_seeds: &[&[u8]] = &[
             b"escrow",
+            ctx.accounts.marketplace.instance_seed(),
             nft_mint_key.as_ref(),
             &[ctx.accounts.escrow.bump],
         ];
 
-        // Transfer NFT back from escrow to seller
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.escrow_token_account.to_account_info(),
-                    to: ctx.accounts.seller_token_account.to_account_info(),
-                    authority: ctx.accounts.escrow.to_account_info(),
-                },
-                &[escrow_seeds],
-            ),
-            1,
-        )?;
+        // Return the NFT to the seller: out of escrow, or thawed in place
+        let custody = ctx.accounts.custody();
+        if listing.is_custodial {
+            custody.release(
+                true,
+                ctx.accounts.seller_token_account.as_ref(),
+                escrow_seeds,
+            )?;
+            custody.close(true, ctx.accounts.seller.as_ref(), escrow_seeds)?;
+        } else {
+            custody.thaw(escrow_seeds)?;
+            if is_seller {
+                token::revoke(CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Revoke {
+                        source: ctx.accounts.seller_token_account.to_account_info(),
+                        authority: ctx.accounts.authority.to_account_info(),
+                    },
+                ))?;
+            } else {
+                custody.clear_delegate(ctx.accounts.authority.as_ref(), escrow_seeds)?;
+            }
+        }
 
         emit!(ListingCancelled {
             nft_mint: listing.nft_mint,
             seller: listing.seller,
         });
+        ctx.accounts.marketplace.listing_closed(listing);
 
-        // listing is closed via the `close = authority` constraint on CancelListing
+        // listing and escrow are closed via the `close = seller` constraints on CancelListing;
+        // the seller paid their rent even when a moderator cancels
         Ok(())
     }
 
-    pub fn buy_nft(ctx: Context<BuyNft>) -> Result<()> {
+    /// Buy a fixed-price listing. `expected_price` and `expected_revision` must match the
+    /// listing as the buyer saw it, so a reprice landing first cannot change what they pay.
+    /// Remaining accounts are the NFT's metadata creators, in order, when its
+    /// collection config enforces royalties, then the revenue split's recipients, in order,
+    /// when the sale is the mint's first with a split and is not held. Each is followed by
+    /// its blocklist PDA.
+    pub fn buy_nft<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BuyNft<'info>>,
+        expected_price: u64,
+        expected_revision: u64,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_BUYING)?;
         let listing = &ctx.accounts.listing;
         require!(listing.is_active, MarketplaceError::ListingNotActive);
+        require!(
+            listing.price == expected_price,
+            MarketplaceError::IncorrectPayment
+        );
+        require!(
+            listing.revision == expected_revision,
+            MarketplaceError::ListingChanged
+        );
 
         let clock = Clock::get()?;
+        listing.require_started(clock.unix_timestamp)?;
         require!(
             clock.unix_timestamp < listing.expiration_time,
             MarketplaceError::ListingExpired
@@ -152,6 +345,16 @@ pub mod anft_marketplace {
             ctx.accounts.buyer.key() != listing.seller,
             MarketplaceError::CannotBuyOwnListing
         );
+        listing.require_open_to(&ctx.accounts.buyer.key())?;
+        BlocklistEntry::require_clear(&ctx.accounts.mint_blocklist, &listing.nft_mint)?;
+        BlocklistEntry::require_clear(&ctx.accounts.buyer_blocklist, &ctx.accounts.buyer.key())?;
+        BlocklistEntry::require_clear(&ctx.accounts.seller_blocklist, &listing.seller)?;
+        BlocklistEntry::require_clear(&ctx.accounts.platform_mint_blocklist, &listing.nft_mint)?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.platform_buyer_blocklist,
+            &ctx.accounts.buyer.key(),
+        )?;
+        BlocklistEntry::require_clear(&ctx.accounts.platform_seller_blocklist, &listing.seller)?;
 
         if !listing.is_auction {
             // Fixed price — exact payment
@@ -160,58 +363,88 @@ pub mod anft_marketplace {
             return Err(MarketplaceError::UseAuctionBidding.into());
         }
 
-        let price = listing.price;
-        let fee_bps = ctx.accounts.marketplace.fee_bps as u64;
-        let fee = price.checked_mul(fee_bps).ok_or(MarketplaceError::Overflow)? / 10_000;
-        let seller_amount = price.checked_sub(fee).ok_or(MarketplaceError::Overflow)?;
+        require!(
+            (listing.hold_period > 0) == ctx.accounts.settlement.is_some(),
+            MarketplaceError::SettlementMismatch
+        );
 
-        // Transfer SOL from buyer to seller
-        anchor_lang::system_program::transfer(
-            CpiContext::new(
-                ctx.accounts.system_program.to_account_info(),
-                anchor_lang::system_program::Transfer {
-                    from: ctx.accounts.buyer.to_account_info(),
-                    to: ctx.accounts.seller.to_account_info(),
-                },
-            ),
-            seller_amount,
+        let price = listing.price;
+        let collection = CollectionMatch::for_sale(
+            listing,
+            ctx.accounts.collection_config.as_deref(),
+            &ctx.accounts.nft_metadata,
+            ctx.accounts.marketplace.instance_seed(),
         )?;
-
-        // Transfer fee to fee recipient
-        if fee > 0 {
-            anchor_lang::system_program::transfer(
-                CpiContext::new(
-                    ctx.accounts.system_program.to_account_info(),
-                    anchor_lang::system_program::Transfer {
-                        from: ctx.accounts.buyer.to_account_info(),
-                        to: ctx.accounts.fee_recipient.to_account_info(),
-                    },
-                ),
-                fee,
-            )?;
+        let mut payout = ctx
+            .accounts
+            .sale_fees()?
+            .payout(price, collection.as_ref())?;
+        listing.charge_commission(&mut payout, price, ctx.accounts.agent_did.as_ref())?;
+        let held_split = payout.apply_split(
+            &ctx.accounts.revenue_split,
+            &listing.seller,
+            ctx.accounts.settlement.is_some(),
+        )?;
+        let fee = payout.total_fee();
+
+        // Transfer SOL from buyer to seller, fee recipient, referrer and creators. A held
+        // sale parks the seller's share, split or not, in the settlement until the dispute
+        // window ends.
+        let (creators, split_recipients) = payout.partition_accounts(ctx.remaining_accounts);
+        let buyer_info = ctx.accounts.buyer.to_account_info();
+        let proceeds_to = match &ctx.accounts.settlement {
+            Some(settlement) => settlement.to_account_info(),
+            None => ctx.accounts.seller.to_account_info(),
+        };
+        SalePayees {
+            seller: &proceeds_to,
+            fee_recipient: &ctx.accounts.fee_recipient,
+            referrer: ctx.accounts.referrer.as_mut(),
+            referrer_wallet: ctx.accounts.referrer_wallet.as_deref(),
+            referrer_blocklist: ctx.accounts.referrer_blocklist.as_deref(),
+            instance_seed: ctx.accounts.marketplace.instance_seed(),
+            creators,
+            split_recipients,
+            agent_wallet: ctx.accounts.agent_wallet.as_deref(),
+            system_program: ctx.accounts.system_program.as_ref(),
         }
+        .pay(&payout, &buyer_info, listing.nft_mint)?;
+        SellerStats::record_sale(&ctx.accounts.seller_stats, payout.marketplace_fee)?;
 
-        // Transfer NFT from escrow to buyer
+        // Transfer NFT from custody to buyer
         let nft_mint_key = ctx.accounts.nft_mint.key();
         let escrow_seeds: &[&[u8]] = &[
             b"escrow",
+            ctx.accounts.marketplace.instance_seed(),
             nft_mint_key.as_ref(),
             &[ctx.accounts.escrow.bump],
         ];
 
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.escrow_token_account.to_account_info(),
-                    to: ctx.accounts.buyer_token_account.to_account_info(),
-                    authority: ctx.accounts.escrow.to_account_info(),
-                },
-                &[escrow_seeds],
-            ),
-            1,
+        let custody = ctx.accounts.custody();
+        custody.release(
+            listing.is_custodial,
+            ctx.accounts.buyer_token_account.as_ref(),
+            escrow_seeds,
+        )?;
+        custody.close(
+            listing.is_custodial,
+            ctx.accounts.seller.as_ref(),
+            escrow_seeds,
         )?;
 
+        if let Some(settlement) = ctx.accounts.settlement.as_mut() {
+            let buyer = ctx.accounts.buyer.key();
+            settlement.hold(
+                listing,
+                buyer,
+                buyer,
+                payout.seller_amount,
+                held_split,
+                ctx.bumps.settlement,
+            )?;
+            ctx.accounts.marketplace.settlement_opened()?;
+        }
+
         emit!(NftPurchased {
             nft_mint: listing.nft_mint,
             buyer: ctx.accounts.buyer.key(),
@@ -219,20 +452,283 @@ pub mod anft_marketplace {
             price,
             fee,
         });
+        ctx.accounts.marketplace.listing_closed(listing);
 
-        // Mark listing as inactive so the same PDA can be reused via init_if_needed
-        let listing = &mut ctx.accounts.listing;
-        listing.is_active = false;
+        // listing and escrow are closed via the `close = seller` constraints on BuyNft;
+        // relisting the NFT initialises them afresh
+        Ok(())
+    }
+
+    /// Buy several fixed-price listings in one transaction, spending at most
+    /// `max_total_price`. Remaining accounts hold, per item: [seller, nft_mint, listing,
+    /// escrow, escrow_token_account, seller_token_account, nft_edition, buyer_token_account,
+    /// seller_stats, collection_config, nft_metadata, mint_blocklist, seller_blocklist,
+    /// revenue_split, platform_mint_blocklist, platform_seller_blocklist], followed by the
+    /// NFT's metadata creators, each with its blocklist PDA, when its collection config
+    /// enforces royalties. The platform blocklists are the default instance's. Pass the
+    /// program ID for custody slots the listing's mode does not use and for
+    /// collection_config when the listing has no collection. With `skip_unavailable`,
+    /// missing, sold or expired listings are skipped instead of failing. Auctions,
+    /// listings reserved for another buyer and listings with a hold period or a
+    /// consignment agent count as unavailable; the last two sell through `buy_nft` or
+    /// `accept_offer`. So must a first sale owed to a revenue split, which fails here.
+    pub fn buy_many<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BuyMany<'info>>,
+        count: u8,
+        max_total_price: u64,
+        skip_unavailable: bool,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_BUYING)?;
+        BlocklistEntry::require_clear(&ctx.accounts.buyer_blocklist, &ctx.accounts.buyer.key())?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.platform_buyer_blocklist,
+            &ctx.accounts.buyer.key(),
+        )?;
+        require!(count > 0, MarketplaceError::InvalidBatchAccounts);
+
+        let clock = Clock::get()?;
+        let buyer = ctx.accounts.buyer.key();
+        let mut total_price: u64 = 0;
+        let mut purchased: u32 = 0;
+        let mut remaining: &'info [AccountInfo<'info>] = ctx.remaining_accounts;
+        for _ in 0..count {
+            let (item_accounts, rest) = take_accounts(remaining, BUY_BATCH_STRIDE)?;
+            let instance_seed = ctx.accounts.marketplace.instance_seed();
+            let listing = live_listing(item_accounts, instance_seed, &buyer, clock.unix_timestamp);
+            let (config_info, nft_metadata) = (&item_accounts[9], &item_accounts[10]);
+            let collection = match &listing {
+                Some(listing) => CollectionMatch::for_sale(
+                    listing,
+                    (config_info.key() != crate::ID).then_some(config_info),
+                    nft_metadata,
+                    instance_seed,
+                )?,
+                // A skipped item still carries the creators of the config the caller passed
+                None => CollectionConfig::load(config_info)?
+                    .map(|config| {
+                        CollectionMatch::resolve(config, nft_metadata, &item_accounts[1].key())
+                    })
+                    .transpose()?,
+            };
+            let royalty_recipients = collection
+                .as_ref()
+                .map_or(0, |collection| collection.royalty_recipients());
+            let (creators, rest) = take_accounts(rest, 2 * royalty_recipients)?;
+            remaining = rest;
+
+            let Some(listing) = listing else {
+                require!(skip_unavailable, MarketplaceError::ListingUnavailable);
+                continue;
+            };
+
+            total_price = total_price
+                .checked_add(listing.price)
+                .ok_or(MarketplaceError::Overflow)?;
+            require!(
+                total_price <= max_total_price,
+                MarketplaceError::PriceCapExceeded
+            );
+
+            ctx.accounts.marketplace.listing_closed(&listing);
+            buy_batch_item(
+                ctx.accounts,
+                listing,
+                item_accounts,
+                collection.as_ref(),
+                creators,
+            )?;
+            purchased += 1;
+        }
+        require!(remaining.is_empty(), MarketplaceError::InvalidBatchAccounts);
+
+        emit!(SweepCompleted {
+            buyer: ctx.accounts.buyer.key(),
+            requested: count as u32,
+            purchased,
+            total_price,
+        });
 
         Ok(())
     }
 
-    pub fn make_offer(ctx: Context<MakeOffer>, amount: u64, duration: i64) -> Result<()> {
-        require!(
-            !ctx.accounts.marketplace.paused,
-            MarketplaceError::MarketplacePaused
-        );
+    /// Dispute a held sale before its proceeds are released, for example when the NFT's
+    /// content hash does not match its attestation. `evidence` is a hash of the buyer's
+    /// case for the arbitrator. The buyer returns the NFT into the settlement's custody
+    /// until the dispute is resolved.
+    pub fn open_dispute(ctx: Context<OpenDispute>, evidence: [u8; 32]) -> Result<()> {
+        let settlement = &mut ctx.accounts.settlement;
+        settlement.open_dispute(Clock::get()?.unix_timestamp)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.buyer_token_account.to_account_info(),
+                    to: ctx.accounts.settlement_token_account.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        emit!(DisputeOpened {
+            nft_mint: settlement.nft_mint,
+            buyer: settlement.buyer,
+            seller: settlement.seller,
+            evidence,
+        });
+
+        Ok(())
+    }
+
+    /// Pay held proceeds to the seller once the dispute window has passed undisputed.
+    /// Permissionless and never paused; the settlement's rent returns to whoever paid it.
+    /// Proceeds owed to a revenue split go to its recipients instead, passed in order as
+    /// remaining accounts, each followed by its blocklist PDA.
+    pub fn release_settlement<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ReleaseSettlement<'info>>,
+    ) -> Result<()> {
+        let settlement = &ctx.accounts.settlement;
+        settlement.require_releasable(Clock::get()?.unix_timestamp)?;
+
+        let amount = settlement.amount;
+        Settlement::release(
+            settlement,
+            amount,
+            ctx.accounts.seller.as_ref(),
+            ctx.accounts.revenue_split.as_deref(),
+            ctx.remaining_accounts,
+            ctx.accounts.marketplace.instance_seed(),
+            ctx.accounts.system_program.as_ref(),
+        )?;
+
+        emit!(SettlementReleased {
+            nft_mint: settlement.nft_mint,
+            buyer: settlement.buyer,
+            seller: settlement.seller,
+            amount,
+        });
+        ctx.accounts.marketplace.settlement_closed();
+
+        // settlement is closed via the `close = rent_payer` constraint on ReleaseSettlement
+        Ok(())
+    }
+
+    /// Arbitrate a disputed sale: refund `refund_amount` of the held proceeds to the buyer
+    /// and release the rest to the seller, or to the revenue split's recipients passed as
+    /// for `release_settlement`. The NFT the buyer returned goes to the seller when
+    /// `return_nft_to_seller` is set and back to the buyer otherwise, whatever the refund,
+    /// so a partial refund can leave the buyer keeping a flawed item. Only the held
+    /// proceeds are refundable: fees, royalties and commission paid at the sale are not.
+    pub fn resolve_dispute<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ResolveDispute<'info>>,
+        refund_amount: u64,
+        return_nft_to_seller: bool,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_role(Role::ListingModerator, &ctx.accounts.authority.key())?;
+        let settlement = &ctx.accounts.settlement;
+        let released = settlement.released_after_refund(refund_amount)?;
+
+        let nft_owner = if return_nft_to_seller {
+            ctx.accounts.seller.to_account_info()
+        } else {
+            ctx.accounts.buyer.to_account_info()
+        };
+        let nft_recipient = nft_owner.key();
+        anchor_spl::associated_token::create_idempotent(CpiContext::new(
+            ctx.accounts.associated_token_program.to_account_info(),
+            anchor_spl::associated_token::Create {
+                payer: ctx.accounts.authority.to_account_info(),
+                associated_token: ctx.accounts.nft_recipient_token_account.to_account_info(),
+                authority: nft_owner,
+                mint: ctx.accounts.nft_mint.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+        ))?;
+
+        let buyer_key = settlement.buyer;
+        let settlement_seeds: &[&[u8]] = &[
+            b"settlement",
+            ctx.accounts.marketplace.instance_seed(),
+            settlement.nft_mint.as_ref(),
+            buyer_key.as_ref(),
+            &[settlement.bump],
+        ];
+        let settlement_info = settlement.to_account_info();
+        let buyer_info = ctx.accounts.buyer.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(
+            CpiContext::new_with_signer(
+                token_program.clone(),
+                Transfer {
+                    from: ctx.accounts.settlement_token_account.to_account_info(),
+                    to: ctx.accounts.nft_recipient_token_account.to_account_info(),
+                    authority: settlement_info.clone(),
+                },
+                &[settlement_seeds],
+            ),
+            1,
+        )?;
+        token::close_account(CpiContext::new_with_signer(
+            token_program,
+            CloseAccount {
+                account: ctx.accounts.settlement_token_account.to_account_info(),
+                destination: buyer_info.clone(),
+                authority: settlement_info.clone(),
+            },
+            &[settlement_seeds],
+        ))?;
+
+        **settlement_info.try_borrow_mut_lamports()? -= refund_amount;
+        **buyer_info.try_borrow_mut_lamports()? += refund_amount;
+        Settlement::release(
+            settlement,
+            released,
+            ctx.accounts.seller.as_ref(),
+            ctx.accounts.revenue_split.as_deref(),
+            ctx.remaining_accounts,
+            ctx.accounts.marketplace.instance_seed(),
+            ctx.accounts.system_program.as_ref(),
+        )?;
+
+        emit!(DisputeResolved {
+            nft_mint: settlement.nft_mint,
+            buyer: settlement.buyer,
+            seller: settlement.seller,
+            refunded: refund_amount,
+            released,
+            nft_recipient,
+        });
+        ctx.accounts.marketplace.settlement_closed();
+
+        // settlement is closed via the `close = rent_payer` constraint on ResolveDispute
+        Ok(())
+    }
+
+    /// Offer `amount` on a listing, escrowing it until accepted or cancelled. An offer at or
+    /// above the listing's auto-accept price instead settles at once, paid straight from the
+    /// offerer, and then needs the optional sale accounts, including the settlement when
+    /// the listing has a hold period. Remaining accounts are then laid out as for `buy_nft`.
+    pub fn make_offer<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MakeOffer<'info>>,
+        amount: u64,
+        duration: i64,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_OFFERS)?;
         require!(amount > 0, MarketplaceError::OfferAmountMustBePositive);
+        grow_legacy_account(
+            &ctx.accounts.offer.to_account_info(),
+            ctx.accounts.offerer.as_ref(),
+            8 + Offer::INIT_SPACE,
+            ctx.accounts.system_program.as_ref(),
+        )?;
         require!(
             ctx.accounts.listing.is_active,
             MarketplaceError::ListingNotActive
@@ -241,20 +737,56 @@ pub mod anft_marketplace {
             ctx.accounts.offerer.key() != ctx.accounts.listing.seller,
             MarketplaceError::CannotOfferOnOwnListing
         );
+        ctx.accounts
+            .listing
+            .require_open_to(&ctx.accounts.offerer.key())?;
+        ctx.accounts
+            .listing
+            .require_started(Clock::get()?.unix_timestamp)?;
+        BlocklistEntry::require_clear(&ctx.accounts.mint_blocklist, &ctx.accounts.nft_mint.key())?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.offerer_blocklist,
+            &ctx.accounts.offerer.key(),
+        )?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.platform_mint_blocklist,
+            &ctx.accounts.nft_mint.key(),
+        )?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.platform_offerer_blocklist,
+            &ctx.accounts.offerer.key(),
+        )?;
 
         let clock = Clock::get()?;
+        if ctx
+            .accounts
+            .listing
+            .auto_accepts(amount, clock.unix_timestamp)
+        {
+            return ctx
+                .accounts
+                .settle(amount, ctx.bumps.settlement, ctx.remaining_accounts);
+        }
+        require!(
+            ctx.accounts.settlement.is_none(),
+            MarketplaceError::SettlementMismatch
+        );
+
+        // Remaking an active offer replaces it rather than opening another
+        let offer = &ctx.accounts.offer;
+        if !offer.is_active || !offer.counted {
+            ctx.accounts.marketplace.offer_opened()?;
+        }
 
         let offer = &mut ctx.accounts.offer;
-        offer.offerer = ctx.accounts.offerer.key();
-        offer.nft_mint = ctx.accounts.nft_mint.key();
-        offer.amount = amount;
-        offer.expiration_time = clock
-            .unix_timestamp
-            .checked_add(duration)
-            .ok_or(MarketplaceError::Overflow)?;
-        offer.is_active = true;
-        offer.created_at = clock.unix_timestamp;
-        offer.bump = ctx.bumps.offer;
+        offer.open(
+            ctx.accounts.offerer.key(),
+            ctx.accounts.nft_mint.key(),
+            amount,
+            duration,
+            ctx.bumps.offer,
+            clock.unix_timestamp,
+        )?;
 
         let offer_escrow = &mut ctx.accounts.offer_escrow;
         offer_escrow.nft_mint = ctx.accounts.nft_mint.key();
@@ -278,6 +810,86 @@ pub mod anft_marketplace {
             offerer: offer.offerer,
             amount,
             expiration_time: offer.expiration_time,
+            pool_backed: false,
+        });
+
+        Ok(())
+    }
+
+    /// Make an offer backed by the offerer's bidding pool instead of its own escrow. The
+    /// pool need only cover `amount` when the offer is accepted, so one deposit can back
+    /// offers on many NFTs.
+    pub fn make_pool_offer(ctx: Context<MakePoolOffer>, amount: u64, duration: i64) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_OFFERS)?;
+        require!(amount > 0, MarketplaceError::OfferAmountMustBePositive);
+        grow_legacy_account(
+            &ctx.accounts.offer.to_account_info(),
+            ctx.accounts.offerer.as_ref(),
+            8 + Offer::INIT_SPACE,
+            ctx.accounts.system_program.as_ref(),
+        )?;
+        require!(
+            ctx.accounts.listing.is_active,
+            MarketplaceError::ListingNotActive
+        );
+        require!(
+            ctx.accounts.offerer.key() != ctx.accounts.listing.seller,
+            MarketplaceError::CannotOfferOnOwnListing
+        );
+        ctx.accounts
+            .listing
+            .require_open_to(&ctx.accounts.offerer.key())?;
+        ctx.accounts
+            .listing
+            .require_started(Clock::get()?.unix_timestamp)?;
+        require!(
+            ctx.accounts.bidding_pool.balance >= amount,
+            MarketplaceError::InsufficientPoolBalance
+        );
+        BlocklistEntry::require_clear(&ctx.accounts.mint_blocklist, &ctx.accounts.nft_mint.key())?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.offerer_blocklist,
+            &ctx.accounts.offerer.key(),
+        )?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.platform_mint_blocklist,
+            &ctx.accounts.nft_mint.key(),
+        )?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.platform_offerer_blocklist,
+            &ctx.accounts.offerer.key(),
+        )?;
+
+        // An escrow-funded offer must be cancelled first, or its SOL would be stranded
+        let offer = &ctx.accounts.offer;
+        require!(
+            !offer.is_active || offer.pool_backed,
+            MarketplaceError::OfferFundingMismatch
+        );
+        if !offer.is_active || !offer.counted {
+            ctx.accounts.marketplace.offer_opened()?;
+        }
+
+        let clock = Clock::get()?;
+        let offer = &mut ctx.accounts.offer;
+        offer.open(
+            ctx.accounts.offerer.key(),
+            ctx.accounts.nft_mint.key(),
+            amount,
+            duration,
+            ctx.bumps.offer,
+            clock.unix_timestamp,
+        )?;
+        offer.pool_backed = true;
+
+        emit!(OfferCreated {
+            nft_mint: offer.nft_mint,
+            offerer: offer.offerer,
+            amount,
+            expiration_time: offer.expiration_time,
+            pool_backed: true,
         });
 
         Ok(())
@@ -293,32 +905,136 @@ pub mod anft_marketplace {
 
         let amount = offer.amount;
 
-        // Return SOL from offer escrow to offerer
-        let offer_escrow_info = ctx.accounts.offer_escrow.to_account_info();
-        let offerer_info = ctx.accounts.offerer.to_account_info();
-        **offer_escrow_info.try_borrow_mut_lamports()? -= amount;
-        **offerer_info.try_borrow_mut_lamports()? += amount;
+        // Return SOL from offer escrow to offerer; pool-backed offers hold none
+        if !offer.pool_backed {
+            let offer_escrow_info = ctx
+                .accounts
+                .offer_escrow
+                .as_ref()
+                .ok_or(MarketplaceError::MissingOfferFunding)?
+                .to_account_info();
+            let offerer_info = ctx.accounts.offerer.to_account_info();
+            **offer_escrow_info.try_borrow_mut_lamports()? -= amount;
+            **offerer_info.try_borrow_mut_lamports()? += amount;
+        }
 
         emit!(OfferCancelled {
             nft_mint: offer.nft_mint,
             offerer: offer.offerer,
             amount,
         });
+        ctx.accounts.marketplace.offer_closed(offer);
 
-        // offer and offer_escrow closed via close constraints
+        // offer and offer_escrow, if any, are closed via close constraints
         Ok(())
     }
 
-    pub fn accept_offer(ctx: Context<AcceptOffer>) -> Result<()> {
-        let listing = &ctx.accounts.listing;
-        let offer = &ctx.accounts.offer;
+    /// Add SOL to the signer's bidding pool, creating it on first deposit.
+    pub fn deposit_to_pool(ctx: Context<DepositToPool>, amount: u64) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_OFFERS)?;
+        require!(amount > 0, MarketplaceError::OfferAmountMustBePositive);
 
-        require!(listing.is_active, MarketplaceError::ListingNotActive);
-        require!(offer.is_active, MarketplaceError::OfferNotActive);
-        require!(
-            ctx.accounts.seller.key() == listing.seller,
-            MarketplaceError::InvalidSeller
+        if ctx.accounts.bidding_pool.owner == Pubkey::default() {
+            ctx.accounts.marketplace.bidding_pool_opened()?;
+            let pool = &mut ctx.accounts.bidding_pool;
+            pool.owner = ctx.accounts.owner.key();
+            pool.bump = ctx.bumps.bidding_pool;
+        }
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.owner.to_account_info(),
+                    to: ctx.accounts.bidding_pool.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let pool = &mut ctx.accounts.bidding_pool;
+        pool.balance = pool
+            .balance
+            .checked_add(amount)
+            .ok_or(MarketplaceError::Overflow)?;
+
+        emit!(BiddingPoolUpdated {
+            owner: pool.owner,
+            balance: pool.balance,
+        });
+
+        Ok(())
+    }
+
+    /// Take SOL back out of the signer's bidding pool. Pool-backed offers the remaining
+    /// balance no longer covers simply fail when accepted.
+    pub fn withdraw_from_pool(ctx: Context<WithdrawFromPool>, amount: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.bidding_pool;
+        pool.debit(amount)?;
+
+        let pool_info = pool.to_account_info();
+        let owner_info = ctx.accounts.owner.to_account_info();
+        **pool_info.try_borrow_mut_lamports()? -= amount;
+        **owner_info.try_borrow_mut_lamports()? += amount;
+
+        emit!(BiddingPoolUpdated {
+            owner: pool.owner,
+            balance: pool.balance,
+        });
+
+        Ok(())
+    }
+
+    /// Close the signer's bidding pool, returning its balance and rent.
+    pub fn close_bidding_pool(ctx: Context<CloseBiddingPool>) -> Result<()> {
+        emit!(BiddingPoolClosed {
+            owner: ctx.accounts.owner.key(),
+            amount: ctx.accounts.bidding_pool.balance,
+        });
+        ctx.accounts.marketplace.bidding_pools_closed(1);
+
+        // bidding_pool is closed via the `close = owner` constraint on CloseBiddingPool
+        Ok(())
+    }
+
+    /// Accept an offer as the seller or their consignment agent. `expected_amount` and
+    /// `expected_revision` must match the offer and listing as the signer saw them. A
+    /// listing with a hold period holds the proceeds in a settlement, whose rent the signer
+    /// pays. Remaining accounts are laid out as for `buy_nft`.
+    pub fn accept_offer<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AcceptOffer<'info>>,
+        expected_amount: u64,
+        expected_revision: u64,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_OFFERS)?;
+        let listing = &ctx.accounts.listing;
+        let offer = &ctx.accounts.offer;
+
+        require!(listing.is_active, MarketplaceError::ListingNotActive);
+        require!(offer.is_active, MarketplaceError::OfferNotActive);
+        require!(
+            offer.amount == expected_amount,
+            MarketplaceError::IncorrectPayment
+        );
+        require!(
+            listing.revision == expected_revision,
+            MarketplaceError::ListingChanged
         );
+        listing.require_manager(
+            &ctx.accounts.authority.key(),
+            ctx.accounts.agent_did.as_ref(),
+        )?;
+        listing.require_open_to(&offer.offerer)?;
+        BlocklistEntry::require_clear(&ctx.accounts.mint_blocklist, &listing.nft_mint)?;
+        BlocklistEntry::require_clear(&ctx.accounts.offerer_blocklist, &offer.offerer)?;
+        BlocklistEntry::require_clear(&ctx.accounts.seller_blocklist, &listing.seller)?;
+        BlocklistEntry::require_clear(&ctx.accounts.platform_mint_blocklist, &listing.nft_mint)?;
+        BlocklistEntry::require_clear(&ctx.accounts.platform_offerer_blocklist, &offer.offerer)?;
+        BlocklistEntry::require_clear(&ctx.accounts.platform_seller_blocklist, &listing.seller)?;
 
         let clock = Clock::get()?;
         require!(
@@ -327,45 +1043,98 @@ pub mod anft_marketplace {
         );
 
         let amount = offer.amount;
-        let fee_bps = ctx.accounts.marketplace.fee_bps as u64;
-        let fee = amount
-            .checked_mul(fee_bps)
-            .ok_or(MarketplaceError::Overflow)?
-            / 10_000;
-        let seller_amount = amount.checked_sub(fee).ok_or(MarketplaceError::Overflow)?;
-
-        // Transfer SOL from offer escrow to seller
-        let offer_escrow_info = ctx.accounts.offer_escrow.to_account_info();
-        let seller_info = ctx.accounts.seller.to_account_info();
-        **offer_escrow_info.try_borrow_mut_lamports()? -= amount;
-        **seller_info.try_borrow_mut_lamports()? += seller_amount;
-
-        // Transfer fee to fee recipient
-        if fee > 0 {
-            let fee_info = ctx.accounts.fee_recipient.to_account_info();
-            **fee_info.try_borrow_mut_lamports()? += fee;
+        let collection = CollectionMatch::for_sale(
+            listing,
+            ctx.accounts.collection_config.as_deref(),
+            &ctx.accounts.nft_metadata,
+            ctx.accounts.marketplace.instance_seed(),
+        )?;
+        let mut payout = ctx
+            .accounts
+            .sale_fees()?
+            .payout(amount, collection.as_ref())?;
+        listing.charge_commission(&mut payout, amount, ctx.accounts.agent_did.as_ref())?;
+        require!(
+            (listing.hold_period > 0) == ctx.accounts.settlement.is_some(),
+            MarketplaceError::SettlementMismatch
+        );
+        let held_split = payout.apply_split(
+            &ctx.accounts.revenue_split,
+            &listing.seller,
+            ctx.accounts.settlement.is_some(),
+        )?;
+        let fee = payout.total_fee();
+
+        // Pay the seller, fee recipient, referrer and creators out of the offer escrow, or
+        // out of the offerer's bidding pool if it still covers the offer. A held sale parks
+        // the seller's share in the settlement, as in `buy_nft`.
+        let (creators, split_recipients) = payout.partition_accounts(ctx.remaining_accounts);
+        let funding_info = if offer.pool_backed {
+            let pool = ctx
+                .accounts
+                .bidding_pool
+                .as_mut()
+                .ok_or(MarketplaceError::MissingOfferFunding)?;
+            pool.debit(amount)?;
+            pool.to_account_info()
+        } else {
+            ctx.accounts
+                .offer_escrow
+                .as_ref()
+                .ok_or(MarketplaceError::MissingOfferFunding)?
+                .to_account_info()
+        };
+        let proceeds_to = match &ctx.accounts.settlement {
+            Some(settlement) => settlement.to_account_info(),
+            None => ctx.accounts.seller.to_account_info(),
+        };
+        SalePayees {
+            seller: &proceeds_to,
+            fee_recipient: &ctx.accounts.fee_recipient,
+            referrer: ctx.accounts.referrer.as_mut(),
+            referrer_wallet: ctx.accounts.referrer_wallet.as_deref(),
+            referrer_blocklist: ctx.accounts.referrer_blocklist.as_deref(),
+            instance_seed: ctx.accounts.marketplace.instance_seed(),
+            creators,
+            split_recipients,
+            agent_wallet: ctx.accounts.agent_wallet.as_deref(),
+            system_program: ctx.accounts.system_program.as_ref(),
         }
+        .pay(&payout, &funding_info, listing.nft_mint)?;
+        SellerStats::record_sale(&ctx.accounts.seller_stats, payout.marketplace_fee)?;
 
-        // Transfer NFT from escrow to offerer (buyer)
+        // Transfer NFT from custody to offerer (buyer)
         let nft_mint_key = ctx.accounts.nft_mint.key();
         let escrow_seeds: &[&[u8]] = &[
             b"escrow",
+            ctx.accounts.marketplace.instance_seed(),
             nft_mint_key.as_ref(),
             &[ctx.accounts.escrow.bump],
         ];
 
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.escrow_token_account.to_account_info(),
-                    to: ctx.accounts.offerer_token_account.to_account_info(),
-                    authority: ctx.accounts.escrow.to_account_info(),
-                },
-                &[escrow_seeds],
-            ),
-            1,
+        let custody = ctx.accounts.custody();
+        custody.release(
+            listing.is_custodial,
+            ctx.accounts.offerer_token_account.as_ref(),
+            escrow_seeds,
         )?;
+        custody.close(
+            listing.is_custodial,
+            ctx.accounts.seller.as_ref(),
+            escrow_seeds,
+        )?;
+
+        if let Some(settlement) = ctx.accounts.settlement.as_mut() {
+            settlement.hold(
+                listing,
+                offer.offerer,
+                ctx.accounts.authority.key(),
+                payout.seller_amount,
+                held_split,
+                ctx.bumps.settlement,
+            )?;
+            ctx.accounts.marketplace.settlement_opened()?;
+        }
 
         emit!(OfferAccepted {
             nft_mint: listing.nft_mint,
@@ -374,657 +1143,6772 @@ pub mod anft_marketplace {
             price: amount,
             fee,
         });
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.listing_closed(listing);
+        marketplace.offer_closed(offer);
 
-        // Mark listing as inactive
-        let listing = &mut ctx.accounts.listing;
-        listing.is_active = false;
-
-        // offer and offer_escrow closed via close constraints
+        // listing and escrow are closed to the seller, and offer and offer_escrow (if any) to
+        // the offerer, via the close constraints on AcceptOffer
         Ok(())
     }
 
+    /// Reprice a listing as the seller or their consignment agent. A listing still in the
+    /// legacy layout cannot record the new revision, so it must go through
+    /// `migrate_listing` first.
     pub fn update_price(ctx: Context<UpdatePrice>, new_price: u64) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_LISTING)?;
         require!(new_price > 0, MarketplaceError::PriceMustBePositive);
 
         let listing = &mut ctx.accounts.listing;
         require!(listing.is_active, MarketplaceError::ListingNotActive);
-        require!(
-            ctx.accounts.seller.key() == listing.seller,
-            MarketplaceError::InvalidSeller
-        );
+        listing.require_manager(
+            &ctx.accounts.authority.key(),
+            ctx.accounts.agent_did.as_ref(),
+        )?;
 
         let old_price = listing.price;
-        listing.price = new_price;
+        listing.set_price(new_price)?;
 
         emit!(PriceUpdated {
             nft_mint: listing.nft_mint,
             old_price,
             new_price,
+            revision: listing.revision,
         });
 
         Ok(())
     }
 
-    pub fn pause_marketplace(ctx: Context<PauseMarketplace>) -> Result<()> {
-        let marketplace = &mut ctx.accounts.marketplace;
+    /// Change a live listing's price, expiration, auction mode or reserved buyer in place,
+    /// keeping the NFT where it is. Unset fields are left as they are.
+    pub fn update_listing(ctx: Context<UpdateListing>, update: ListingUpdate) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(update.pause_flags())?;
+
+        let listing = &mut ctx.accounts.listing;
+        require!(listing.is_active, MarketplaceError::ListingNotActive);
+        listing.require_manager(
+            &ctx.accounts.authority.key(),
+            ctx.accounts.agent_did.as_ref(),
+        )?;
+
+        listing.apply(&update, Clock::get()?.unix_timestamp)?;
+
+        emit!(ListingUpdated {
+            nft_mint: listing.nft_mint,
+            price: listing.price,
+            expiration_time: listing.expiration_time,
+            is_auction: listing.is_auction,
+            reserved_buyer: listing.reserved_buyer,
+            revision: listing.revision,
+        });
+
+        Ok(())
+    }
+
+    /// Authorise a gallery or curator to manage the listing for `agent.commission_bps` of
+    /// the sale price, or remove the current agent with `None`. Seller only.
+    pub fn set_agent(ctx: Context<UpdatePrice>, agent: Option<ConsignmentAgent>) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_LISTING)?;
+
+        let listing = &mut ctx.accounts.listing;
+        require!(listing.is_active, MarketplaceError::ListingNotActive);
         require!(
-            ctx.accounts.admin.key() == marketplace.admin,
-            MarketplaceError::Unauthorized
+            ctx.accounts.authority.key() == listing.seller,
+            MarketplaceError::InvalidSeller
         );
-        require!(!marketplace.paused, MarketplaceError::AlreadyPaused);
 
-        marketplace.paused = true;
+        listing.set_agent(agent.clone())?;
 
-        emit!(MarketplacePausedEvent { paused: true });
+        emit!(ConsignmentAgentUpdated {
+            nft_mint: listing.nft_mint,
+            agent,
+            revision: listing.revision,
+        });
 
         Ok(())
     }
 
-    pub fn unpause_marketplace(ctx: Context<UnpauseMarketplace>) -> Result<()> {
-        let marketplace = &mut ctx.accounts.marketplace;
+    /// Set or clear the price at which offers on the listing are accepted automatically.
+    pub fn set_auto_accept_price(
+        ctx: Context<UpdatePrice>,
+        auto_accept_price: Option<u64>,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_LISTING)?;
+
+        let listing = &mut ctx.accounts.listing;
+        require!(listing.is_active, MarketplaceError::ListingNotActive);
+        listing.require_manager(
+            &ctx.accounts.authority.key(),
+            ctx.accounts.agent_did.as_ref(),
+        )?;
+
+        listing.set_auto_accept_price(auto_accept_price)?;
+
+        emit!(AutoAcceptPriceUpdated {
+            nft_mint: listing.nft_mint,
+            auto_accept_price,
+            revision: listing.revision,
+        });
+
+        Ok(())
+    }
+
+    /// Custodial `list_nft` for many NFTs at once. Remaining accounts hold, per item:
+    /// [nft_mint, listing, escrow, seller_token_account, escrow_token_account,
+    /// mint_blocklist, nft_metadata, collection_config], passing the program ID for
+    /// collection_config when the NFT has no verified collection.
+    /// Items are processed in order until the compute budget runs low.
+    pub fn list_nfts_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ListNftsBatch<'info>>,
+        items: Vec<ListingTerms>,
+    ) -> Result<()> {
+        for terms in &items {
+            ctx.accounts
+                .marketplace
+                .require_unpaused(terms.pause_flags())?;
+        }
+        BlocklistEntry::require_clear(&ctx.accounts.seller_blocklist, &ctx.accounts.seller.key())?;
+        let accounts = batch_chunks(ctx.remaining_accounts, items.len(), LIST_BATCH_STRIDE)?;
+
+        let clock = Clock::get()?;
+        let mut processed: u32 = 0;
+        for (terms, item_accounts) in items.iter().zip(accounts) {
+            if !has_compute_for(LIST_ITEM_COMPUTE_UNITS) {
+                break;
+            }
+            list_batch_item(ctx.accounts, terms, item_accounts, &clock)?;
+            processed += 1;
+        }
+
+        ctx.accounts.marketplace.listings_opened(processed as u64)?;
+
+        emit!(BatchProcessed {
+            seller: ctx.accounts.seller.key(),
+            requested: items.len() as u32,
+            processed,
+        });
+
+        Ok(())
+    }
+
+    /// Seller-side `cancel_listing` for many listings at once. Remaining accounts hold,
+    /// per item: [nft_mint, listing, escrow, escrow_token_account, seller_token_account,
+    /// nft_edition]. Pass the program ID for slots the listing's mode does not use.
+    pub fn cancel_listings_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CancelListingsBatch<'info>>,
+        count: u8,
+    ) -> Result<()> {
+        let accounts = batch_chunks(ctx.remaining_accounts, count as usize, CANCEL_BATCH_STRIDE)?;
+
+        let mut processed: u32 = 0;
+        for item_accounts in accounts {
+            if !has_compute_for(CANCEL_ITEM_COMPUTE_UNITS) {
+                break;
+            }
+            cancel_batch_item(ctx.accounts, item_accounts)?;
+            processed += 1;
+        }
+
+        emit!(BatchProcessed {
+            seller: ctx.accounts.seller.key(),
+            requested: count as u32,
+            processed,
+        });
+
+        Ok(())
+    }
+
+    /// `update_price` for many listings at once. Remaining accounts hold one
+    /// listing per entry in `new_prices`; legacy listings must be migrated first.
+    pub fn update_prices_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdatePricesBatch<'info>>,
+        new_prices: Vec<u64>,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_LISTING)?;
+        let accounts = batch_chunks(ctx.remaining_accounts, new_prices.len(), 1)?;
+
+        let mut processed: u32 = 0;
+        for (new_price, item_accounts) in new_prices.iter().zip(accounts) {
+            if !has_compute_for(REPRICE_ITEM_COMPUTE_UNITS) {
+                break;
+            }
+            require!(*new_price > 0, MarketplaceError::PriceMustBePositive);
+
+            let mut listing = Account::<Listing>::try_from(&item_accounts[0])?;
+            require!(
+                is_listing_pda(
+                    &listing.key(),
+                    ctx.accounts.marketplace.instance_seed(),
+                    &listing.nft_mint,
+                    listing.bump,
+                ),
+                MarketplaceError::InvalidBatchAccounts
+            );
+            require!(listing.is_active, MarketplaceError::ListingNotActive);
+            require!(
+                ctx.accounts.seller.key() == listing.seller,
+                MarketplaceError::InvalidSeller
+            );
+
+            let old_price = listing.price;
+            listing.set_price(*new_price)?;
+            listing.exit(&crate::ID)?;
+
+            emit!(PriceUpdated {
+                nft_mint: listing.nft_mint,
+                old_price,
+                new_price: *new_price,
+                revision: listing.revision,
+            });
+            processed += 1;
+        }
+
+        emit!(BatchProcessed {
+            seller: ctx.accounts.seller.key(),
+            requested: new_prices.len() as u32,
+            processed,
+        });
+
+        Ok(())
+    }
+
+    /// Set how `nft_mint`'s first sale is split between its collaborators. Only the mint
+    /// authority may, before any token is minted, so no sale can precede it; the split is
+    /// fixed once set.
+    pub fn set_revenue_split(
+        ctx: Context<SetRevenueSplit>,
+        recipients: Vec<FeeShare>,
+    ) -> Result<()> {
+        RevenueSplit::validate_recipients(&recipients)?;
+
+        let split = &mut ctx.accounts.revenue_split;
+        split.nft_mint = ctx.accounts.nft_mint.key();
+        split.authority = ctx.accounts.authority.key();
+        split.recipients = recipients.clone();
+        split.bump = ctx.bumps.revenue_split;
+
+        emit!(RevenueSplitUpdated {
+            nft_mint: split.nft_mint,
+            recipients,
+        });
+
+        Ok(())
+    }
+
+    /// Open `seller`'s stats so their sales count toward the fee tiers. Anyone may pay
+    /// for it; sales before it exists are not recorded.
+    pub fn open_seller_stats(ctx: Context<OpenSellerStats>, seller: Pubkey) -> Result<()> {
+        let stats = &mut ctx.accounts.seller_stats;
+        stats.seller = seller;
+        stats.bump = ctx.bumps.seller_stats;
+        Ok(())
+    }
+
+    /// Register a partner that earns `share_bps` of the marketplace fee on sales it refers.
+    pub fn register_referrer(
+        ctx: Context<RegisterReferrer>,
+        wallet: Pubkey,
+        share_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_role(Role::FeeManager, &ctx.accounts.authority.key())?;
         require!(
-            ctx.accounts.admin.key() == marketplace.admin,
-            MarketplaceError::Unauthorized
+            share_bps <= Referrer::MAX_SHARE_BPS,
+            MarketplaceError::ReferralShareTooHigh
         );
-        require!(marketplace.paused, MarketplaceError::NotPaused);
 
-        marketplace.paused = false;
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.wallet = wallet;
+        referrer.share_bps = share_bps;
+        referrer.total_earned = 0;
+        referrer.bump = ctx.bumps.referrer;
 
-        emit!(MarketplacePausedEvent { paused: false });
+        emit!(ReferrerUpdated { wallet, share_bps });
 
         Ok(())
     }
 
-    pub fn update_fee(ctx: Context<UpdateFee>, new_fee_bps: u16) -> Result<()> {
-        require!(new_fee_bps <= 1000, MarketplaceError::FeeTooHigh);
-
-        let marketplace = &mut ctx.accounts.marketplace;
+    pub fn update_referrer(ctx: Context<UpdateReferrer>, share_bps: u16) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_role(Role::FeeManager, &ctx.accounts.authority.key())?;
         require!(
-            ctx.accounts.admin.key() == marketplace.admin,
-            MarketplaceError::Unauthorized
+            share_bps <= Referrer::MAX_SHARE_BPS,
+            MarketplaceError::ReferralShareTooHigh
         );
 
-        let old_fee_bps = marketplace.fee_bps;
-        marketplace.fee_bps = new_fee_bps;
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.share_bps = share_bps;
+
+        emit!(ReferrerUpdated {
+            wallet: referrer.wallet,
+            share_bps,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_referrer(ctx: Context<RemoveReferrer>) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_role(Role::FeeManager, &ctx.accounts.authority.key())?;
 
-        emit!(FeeUpdated {
-            old_fee_bps,
-            new_fee_bps,
+        emit!(ReferrerRemoved {
+            wallet: ctx.accounts.referrer.wallet,
         });
 
+        // referrer is closed via the `close = authority` constraint on RemoveReferrer
         Ok(())
     }
 
-    pub fn update_fee_recipient(ctx: Context<UpdateFeeRecipient>) -> Result<()> {
+    /// Flag a mint or wallet so it cannot be listed, bought, offered on or paid. Entries on
+    /// the default instance also block sales and offers on every partner instance.
+    pub fn flag_blocklisted(ctx: Context<FlagBlocklisted>, key: Pubkey, reason: u16) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_role(Role::ListingModerator, &ctx.accounts.authority.key())?;
+
+        let clock = Clock::get()?;
+        let entry = &mut ctx.accounts.blocklist_entry;
+        entry.key = key;
+        entry.reason = reason;
+        entry.flagged_by = ctx.accounts.authority.key();
+        entry.flagged_at = clock.unix_timestamp;
+        entry.bump = ctx.bumps.blocklist_entry;
+
+        emit!(KeyBlocklisted {
+            key,
+            reason,
+            flagged_by: entry.flagged_by,
+        });
+
+        Ok(())
+    }
+
+    pub fn unflag_blocklisted(ctx: Context<UnflagBlocklisted>) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_role(Role::ListingModerator, &ctx.accounts.authority.key())?;
+
+        emit!(KeyUnblocklisted {
+            key: ctx.accounts.blocklist_entry.key,
+        });
+
+        // blocklist_entry is closed via the `close = authority` constraint
+        Ok(())
+    }
+
+    /// Nominate `new_admin`; the transfer completes when they sign `accept_admin`.
+    /// Proposing again replaces the pending nominee.
+    pub fn propose_admin(ctx: Context<TransferAdmin>, new_admin: Pubkey) -> Result<()> {
         let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.require_migrated()?;
         require!(
             ctx.accounts.admin.key() == marketplace.admin,
             MarketplaceError::Unauthorized
         );
         require!(
-            ctx.accounts.new_fee_recipient.key() != Pubkey::default(),
-            MarketplaceError::InvalidFeeRecipient
+            new_admin != Pubkey::default() && new_admin != marketplace.admin,
+            MarketplaceError::InvalidAdmin
+        );
+
+        marketplace.pending_admin = Some(new_admin);
+
+        emit!(AdminTransferProposed {
+            admin: marketplace.admin,
+            pending_admin: new_admin,
+        });
+
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.require_migrated()?;
+        require!(
+            marketplace.pending_admin == Some(ctx.accounts.new_admin.key()),
+            MarketplaceError::NotPendingAdmin
         );
 
-        let old_recipient = marketplace.fee_recipient;
-        marketplace.fee_recipient = ctx.accounts.new_fee_recipient.key();
+        let old_admin = marketplace.admin;
+        marketplace.admin = ctx.accounts.new_admin.key();
+        marketplace.pending_admin = None;
 
-        emit!(FeeRecipientUpdated {
-            old_recipient,
-            new_recipient: marketplace.fee_recipient,
+        emit!(AdminTransferred {
+            old_admin,
+            new_admin: marketplace.admin,
         });
 
         Ok(())
     }
 
-    pub fn emergency_withdraw(ctx: Context<EmergencyWithdraw>, amount: u64) -> Result<()> {
+    /// Give up the admin key for good. Roles keep their current holders but can no
+    /// longer be granted or revoked.
+    pub fn renounce_admin(ctx: Context<TransferAdmin>) -> Result<()> {
         let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.require_migrated()?;
         require!(
             ctx.accounts.admin.key() == marketplace.admin,
             MarketplaceError::Unauthorized
         );
-        require!(amount > 0, MarketplaceError::NothingToWithdraw);
 
-        let marketplace_info = marketplace.to_account_info();
-        let admin_info = ctx.accounts.admin.to_account_info();
+        marketplace.admin = Pubkey::default();
+        marketplace.pending_admin = None;
+
+        emit!(AdminRenounced {
+            admin: ctx.accounts.admin.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Hand `role` to `holder`, replacing its current holder.
+    pub fn grant_role(ctx: Context<ManageRole>, role: Role, holder: Pubkey) -> Result<()> {
+        ctx.accounts.marketplace.require_migrated()?;
+        require!(
+            ctx.accounts.admin.key() == ctx.accounts.marketplace.admin,
+            MarketplaceError::Unauthorized
+        );
+        require!(
+            holder != Pubkey::default(),
+            MarketplaceError::InvalidRoleHolder
+        );
 
-        let rent = Rent::get()?;
-        let min_balance = rent.minimum_balance(marketplace_info.data_len());
-        let available = marketplace_info
-            .lamports()
-            .checked_sub(min_balance)
-            .ok_or(MarketplaceError::NothingToWithdraw)?;
-        let withdraw_amount = amount.min(available);
-        require!(withdraw_amount > 0, MarketplaceError::NothingToWithdraw);
+        *ctx.accounts.marketplace.role_holder_mut(role) = holder;
 
-        **marketplace_info.try_borrow_mut_lamports()? -= withdraw_amount;
-        **admin_info.try_borrow_mut_lamports()? += withdraw_amount;
+        emit!(RoleGranted { role, holder });
 
         Ok(())
     }
-}
 
-// ─── Account Contexts ────────────────────────────────────────────────────────
+    /// Leave `role` unheld; its instructions are unavailable until it is granted again.
+    pub fn revoke_role(ctx: Context<ManageRole>, role: Role) -> Result<()> {
+        ctx.accounts.marketplace.require_migrated()?;
+        require!(
+            ctx.accounts.admin.key() == ctx.accounts.marketplace.admin,
+            MarketplaceError::Unauthorized
+        );
 
-#[derive(Accounts)]
-pub struct InitializeMarketplace<'info> {
-    #[account(mut)]
-    pub admin: Signer<'info>,
+        let holder = std::mem::take(ctx.accounts.marketplace.role_holder_mut(role));
 
-    #[account(
-        init,
-        payer = admin,
-        space = 8 + Marketplace::INIT_SPACE,
-        seeds = [b"marketplace"],
-        bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+        emit!(RoleRevoked { role, holder });
 
-    /// CHECK: Fee recipient, validated by admin
-    pub fee_recipient: UncheckedAccount<'info>,
+        Ok(())
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    /// Pause the operations in `flags`, a mask of `Marketplace::PAUSE_*` bits.
+    /// Cancellations, refunds, settlement releases and loan repayments are never paused.
+    pub fn pause_marketplace(ctx: Context<PauseMarketplace>, flags: u8) -> Result<()> {
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.require_migrated()?;
+        marketplace.require_role(Role::Pauser, &ctx.accounts.authority.key())?;
+        Marketplace::validate_pause_flags(flags)?;
+        require!(
+            marketplace.paused & flags != flags,
+            MarketplaceError::AlreadyPaused
+        );
 
-#[derive(Accounts)]
-pub struct ListNft<'info> {
-    #[account(mut)]
-    pub seller: Signer<'info>,
+        marketplace.paused |= flags;
 
-    #[account(
-        mut,
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+        emit!(MarketplacePausedEvent {
+            paused: marketplace.paused,
+        });
 
-    pub nft_mint: Account<'info, Mint>,
+        Ok(())
+    }
 
-    /// Listing PDA — init_if_needed so a previously-purchased NFT can be re-listed
-    #[account(
-        init_if_needed,
-        payer = seller,
-        space = 8 + Listing::INIT_SPACE,
-        seeds = [b"listing", nft_mint.key().as_ref()],
-        bump,
-    )]
-    pub listing: Account<'info, Listing>,
+    /// Resume the operations in `flags`.
+    pub fn unpause_marketplace(ctx: Context<UnpauseMarketplace>, flags: u8) -> Result<()> {
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.require_migrated()?;
+        marketplace.require_role(Role::Pauser, &ctx.accounts.authority.key())?;
+        Marketplace::validate_pause_flags(flags)?;
+        require!(marketplace.paused & flags != 0, MarketplaceError::NotPaused);
 
-    /// Escrow authority PDA — init_if_needed so it persists across listings
-    #[account(
-        init_if_needed,
-        payer = seller,
-        space = 8 + Escrow::INIT_SPACE,
-        seeds = [b"escrow", nft_mint.key().as_ref()],
-        bump,
-    )]
-    pub escrow: Account<'info, Escrow>,
+        marketplace.paused &= !flags;
 
-    #[account(
-        mut,
-        associated_token::mint = nft_mint,
-        associated_token::authority = seller,
-    )]
-    pub seller_token_account: Account<'info, TokenAccount>,
+        emit!(MarketplacePausedEvent {
+            paused: marketplace.paused,
+        });
 
+        Ok(())
+    }
+
+    /// Propose a fee, fee tier, collection config, treasury or emergency withdrawal
+    /// change. It can be executed once `PendingChange::DELAY` has passed, giving sellers
+    /// notice before it applies.
+    pub fn queue_admin_change(ctx: Context<QueueAdminChange>, change: AdminChange) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_role(change.role(), &ctx.accounts.authority.key())?;
+        change.validate()?;
+
+        let clock = Clock::get()?;
+        let pending_change = &mut ctx.accounts.pending_change;
+        pending_change.change = change.clone();
+        pending_change.queued_at = clock.unix_timestamp;
+        pending_change.executable_at = clock
+            .unix_timestamp
+            .checked_add(PendingChange::DELAY)
+            .ok_or(MarketplaceError::Overflow)?;
+        pending_change.bump = ctx.bumps.pending_change;
+
+        emit!(FeeChangeQueued {
+            change,
+            executable_at: pending_change.executable_at,
+        });
+
+        Ok(())
+    }
+
+    /// Apply a queued change whose timelock has elapsed.
+    pub fn execute_admin_change(ctx: Context<ExecuteAdminChange>) -> Result<()> {
+        ctx.accounts.marketplace.require_role(
+            ctx.accounts.pending_change.change.role(),
+            &ctx.accounts.authority.key(),
+        )?;
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp >= ctx.accounts.pending_change.executable_at,
+            MarketplaceError::TimelockNotElapsed
+        );
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        match ctx.accounts.pending_change.change.clone() {
+            AdminChange::Fee { new_fee_bps } => {
+                let old_fee_bps = marketplace.fee_bps;
+                marketplace.fee_bps = new_fee_bps;
+
+                emit!(FeeUpdated {
+                    old_fee_bps,
+                    new_fee_bps,
+                });
+            }
+            AdminChange::FeeRecipient { new_fee_recipient } => {
+                let old_recipient = marketplace.fee_recipient;
+                marketplace.fee_recipient = new_fee_recipient;
+
+                emit!(FeeRecipientUpdated {
+                    old_recipient,
+                    new_recipient: new_fee_recipient,
+                });
+            }
+            AdminChange::EmergencyWithdraw { amount } => {
+                marketplace.require_unpaused(Marketplace::PAUSE_WITHDRAWALS)?;
+
+                // Withdraw collected SOL fees to the treasurer, bypassing the split
+                let treasury = ctx
+                    .accounts
+                    .treasury
+                    .as_ref()
+                    .ok_or(MarketplaceError::NothingToWithdraw)?;
+                let treasury_info = treasury.to_account_info();
+                let authority_info = ctx.accounts.authority.to_account_info();
+
+                let available = Treasury::distributable_lamports(&treasury_info)?;
+                let withdraw_amount = amount.min(available);
+                require!(withdraw_amount > 0, MarketplaceError::NothingToWithdraw);
+
+                **treasury_info.try_borrow_mut_lamports()? -= withdraw_amount;
+                **authority_info.try_borrow_mut_lamports()? += withdraw_amount;
+            }
+            AdminChange::FeeTiers { tiers } => {
+                let fee_tiers = ctx
+                    .accounts
+                    .fee_tiers
+                    .as_mut()
+                    .ok_or(MarketplaceError::MissingChangeAccount)?;
+                fee_tiers.tiers = tiers.clone();
+                fee_tiers.bump = ctx.bumps.fee_tiers.unwrap_or(fee_tiers.bump);
+
+                emit!(FeeTiersUpdated { tiers });
+            }
+            AdminChange::CollectionConfig { collection, config } => {
+                let collection_config = ctx
+                    .accounts
+                    .collection_config
+                    .as_mut()
+                    .ok_or(MarketplaceError::MissingChangeAccount)?;
+                collection_config.collection = collection;
+                collection_config.fee_bps = config.fee_bps;
+                collection_config.enforce_royalties = config.enforce_royalties;
+                collection_config.min_listing_duration = config.min_listing_duration;
+                collection_config.allowed_currencies = config.allowed_currencies.clone();
+                collection_config.bump = ctx
+                    .bumps
+                    .collection_config
+                    .unwrap_or(collection_config.bump);
+
+                emit!(CollectionConfigUpdated { collection, config });
+            }
+            AdminChange::RemoveCollectionConfig { collection } => {
+                let collection_config = ctx
+                    .accounts
+                    .collection_config
+                    .as_ref()
+                    .ok_or(MarketplaceError::MissingChangeAccount)?;
+                // A config created just now by `init_if_needed` has no collection yet
+                require_keys_eq!(
+                    collection_config.collection,
+                    collection,
+                    MarketplaceError::MissingCollectionConfig
+                );
+                collection_config.close(ctx.accounts.authority.to_account_info())?;
+
+                emit!(CollectionConfigRemoved { collection });
+            }
+            AdminChange::TreasuryRecipients { recipients } => {
+                let treasury = ctx
+                    .accounts
+                    .treasury
+                    .as_mut()
+                    .ok_or(MarketplaceError::MissingChangeAccount)?;
+                let created = treasury.recipients.is_empty();
+                treasury.recipients = recipients.clone();
+                treasury.bump = ctx.bumps.treasury.unwrap_or(treasury.bump);
+
+                emit!(TreasuryRecipientsUpdated { recipients });
+                if created {
+                    let old_recipient = marketplace.fee_recipient;
+                    marketplace.fee_recipient = treasury.key();
+
+                    emit!(FeeRecipientUpdated {
+                        old_recipient,
+                        new_recipient: marketplace.fee_recipient,
+                    });
+                }
+            }
+        }
+
+        // pending_change is closed via the `close = authority` constraint on ExecuteAdminChange
+        Ok(())
+    }
+
+    pub fn cancel_admin_change(ctx: Context<CancelAdminChange>) -> Result<()> {
+        ctx.accounts.marketplace.require_role(
+            ctx.accounts.pending_change.change.role(),
+            &ctx.accounts.authority.key(),
+        )?;
+
+        emit!(FeeChangeCancelled {
+            change: ctx.accounts.pending_change.change.clone(),
+        });
+
+        // pending_change is closed via the `close = authority` constraint on CancelAdminChange
+        Ok(())
+    }
+
+    /// Permissionless: split the treasury's SOL above rent between its recipients.
+    /// Remaining accounts are the recipient wallets, in the treasury's order, each
+    /// followed by its blocklist PDA. Fails while any recipient is blocklisted.
+    pub fn distribute_fees<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DistributeFees<'info>>,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_WITHDRAWALS)?;
+        let treasury_info = ctx.accounts.treasury.to_account_info();
+        let total = Treasury::distributable_lamports(&treasury_info)?;
+        require!(total > 0, MarketplaceError::NothingToWithdraw);
+
+        let recipients = &ctx.accounts.treasury.recipients;
+        require!(
+            ctx.remaining_accounts.len() == 2 * recipients.len(),
+            MarketplaceError::InvalidTreasuryRecipients
+        );
+        let instance_seed = ctx.accounts.marketplace.instance_seed();
+
+        let mut distributed: u64 = 0;
+        for (share, pair) in recipients.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            let [recipient_info, blocklist] = pair else {
+                return err!(MarketplaceError::InvalidTreasuryRecipients);
+            };
+            require_keys_eq!(
+                recipient_info.key(),
+                share.recipient,
+                MarketplaceError::InvalidTreasuryRecipients
+            );
+            BlocklistEntry::require_clear_at(blocklist, instance_seed, &share.recipient)?;
+            let amount = share.portion_of(total)?;
+            **treasury_info.try_borrow_mut_lamports()? -= amount;
+            **recipient_info.try_borrow_mut_lamports()? += amount;
+            distributed += amount;
+        }
+
+        emit!(FeesDistributed {
+            mint: Pubkey::default(),
+            amount: distributed,
+        });
+
+        Ok(())
+    }
+
+    /// Permissionless: split the treasury's balance of an SPL token between its
+    /// recipients. Remaining accounts are the recipients' token accounts for `mint`,
+    /// in the treasury's order, each followed by its owner's blocklist PDA. Fails while
+    /// any recipient is blocklisted.
+    pub fn distribute_token_fees<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DistributeTokenFees<'info>>,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_WITHDRAWALS)?;
+        let total = ctx.accounts.treasury_token_account.amount;
+        require!(total > 0, MarketplaceError::NothingToWithdraw);
+
+        let recipients = &ctx.accounts.treasury.recipients;
+        require!(
+            ctx.remaining_accounts.len() == 2 * recipients.len(),
+            MarketplaceError::InvalidTreasuryRecipients
+        );
+        let instance_seed = ctx.accounts.marketplace.instance_seed();
+
+        let treasury_seeds: &[&[u8]] = &[
+            b"treasury",
+            ctx.accounts.marketplace.instance_seed(),
+            &[ctx.accounts.treasury.bump],
+        ];
+        let mut distributed: u64 = 0;
+        for (share, pair) in recipients.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            let [recipient_info, blocklist] = pair else {
+                return err!(MarketplaceError::InvalidTreasuryRecipients);
+            };
+            let recipient_token_account = Account::<TokenAccount>::try_from(recipient_info)?;
+            require!(
+                recipient_token_account.owner == share.recipient
+                    && recipient_token_account.mint == ctx.accounts.mint.key(),
+                MarketplaceError::InvalidTreasuryRecipients
+            );
+            BlocklistEntry::require_clear_at(blocklist, instance_seed, &share.recipient)?;
+
+            let amount = share.portion_of(total)?;
+            if amount == 0 {
+                continue;
+            }
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.treasury_token_account.to_account_info(),
+                        to: recipient_info.clone(),
+                        authority: ctx.accounts.treasury.to_account_info(),
+                    },
+                    &[treasury_seeds],
+                ),
+                amount,
+            )?;
+            distributed += amount;
+        }
+
+        emit!(FeesDistributed {
+            mint: ctx.accounts.mint.key(),
+            amount: distributed,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_loan_offer(
+        ctx: Context<CreateLoanOffer>,
+        collection: Pubkey,
+        principal: u64,
+        apr_bps: u16,
+        duration: i64,
+    ) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_LENDING)?;
+        require!(principal > 0, MarketplaceError::PrincipalMustBePositive);
+        require!(apr_bps <= 50_000, MarketplaceError::AprTooHigh);
+        require!(
+            (86400..=365 * 86400).contains(&duration),
+            MarketplaceError::InvalidLoanDuration
+        );
+        BlocklistEntry::require_clear(&ctx.accounts.lender_blocklist, &ctx.accounts.lender.key())?;
+
+        let clock = Clock::get()?;
+
+        let loan_offer = &mut ctx.accounts.loan_offer;
+        loan_offer.lender = ctx.accounts.lender.key();
+        loan_offer.collection = collection;
+        loan_offer.principal = principal;
+        loan_offer.apr_bps = apr_bps;
+        loan_offer.duration = duration;
+        loan_offer.created_at = clock.unix_timestamp;
+        loan_offer.bump = ctx.bumps.loan_offer;
+
+        // Principal is held by the loan offer PDA until a borrower takes it
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.lender.to_account_info(),
+                    to: ctx.accounts.loan_offer.to_account_info(),
+                },
+            ),
+            principal,
+        )?;
+
+        ctx.accounts.marketplace.loan_offer_opened()?;
+
+        emit!(LoanOfferCreated {
+            lender: ctx.accounts.lender.key(),
+            collection,
+            principal,
+            apr_bps,
+            duration,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_loan_offer(ctx: Context<CancelLoanOffer>) -> Result<()> {
+        ctx.accounts.marketplace.loan_offer_closed();
+        let loan_offer = &ctx.accounts.loan_offer;
+
+        // Principal and rent both return to the lender via the `close = lender` constraint
+        emit!(LoanOfferCancelled {
+            lender: loan_offer.lender,
+            collection: loan_offer.collection,
+            principal: loan_offer.principal,
+        });
+
+        Ok(())
+    }
+
+    pub fn take_loan(ctx: Context<TakeLoan>) -> Result<()> {
+        ctx.accounts
+            .marketplace
+            .require_unpaused(Marketplace::PAUSE_LENDING)?;
+        require!(
+            ctx.accounts.borrower.key() != ctx.accounts.loan_offer.lender,
+            MarketplaceError::CannotBorrowFromSelf
+        );
+        require!(
+            ctx.accounts.borrower_token_account.amount == 1,
+            MarketplaceError::SellerDoesNotOwnNft
+        );
+        BlocklistEntry::require_clear(&ctx.accounts.mint_blocklist, &ctx.accounts.nft_mint.key())?;
+        BlocklistEntry::require_clear(
+            &ctx.accounts.borrower_blocklist,
+            &ctx.accounts.borrower.key(),
+        )?;
+        BlocklistEntry::require_clear(&ctx.accounts.lender_blocklist, &ctx.accounts.lender.key())?;
+
+        // The NFT must belong to the verified collection the lender is lending against
+        let nft_mint_key = ctx.accounts.nft_mint.key();
+        let collection =
+            MetadataPrefix::load(&ctx.accounts.nft_metadata, &nft_mint_key)?.verified_collection();
+        require!(
+            collection == Some(ctx.accounts.loan_offer.collection),
+            MarketplaceError::InvalidCollection
+        );
+
+        let clock = Clock::get()?;
+        let loan_offer = &ctx.accounts.loan_offer;
+
+        let loan = &mut ctx.accounts.loan;
+        loan.borrower = ctx.accounts.borrower.key();
+        loan.lender = loan_offer.lender;
+        loan.nft_mint = nft_mint_key;
+        loan.collection = loan_offer.collection;
+        loan.principal = loan_offer.principal;
+        loan.apr_bps = loan_offer.apr_bps;
+        loan.start_time = clock.unix_timestamp;
+        loan.due_time = clock
+            .unix_timestamp
+            .checked_add(loan_offer.duration)
+            .ok_or(MarketplaceError::Overflow)?;
+        loan.bump = ctx.bumps.loan;
+
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.nft_mint = nft_mint_key;
+        escrow.bump = ctx.bumps.escrow;
+
+        // Transfer NFT from borrower to escrow token account
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.borrower_token_account.to_account_info(),
+                    to: ctx.accounts.escrow_token_account.to_account_info(),
+                    authority: ctx.accounts.borrower.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        // Pay out the principal from the loan offer PDA to the borrower
+        let principal = loan.principal;
+        let loan_offer_info = ctx.accounts.loan_offer.to_account_info();
+        let borrower_info = ctx.accounts.borrower.to_account_info();
+        **loan_offer_info.try_borrow_mut_lamports()? -= principal;
+        **borrower_info.try_borrow_mut_lamports()? += principal;
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.loan_offer_closed();
+        marketplace.loan_opened()?;
+
+        emit!(LoanStarted {
+            nft_mint: nft_mint_key,
+            borrower: loan.borrower,
+            lender: loan.lender,
+            principal,
+            apr_bps: loan.apr_bps,
+            due_time: loan.due_time,
+        });
+
+        // loan_offer is closed via the `close = lender` constraint on TakeLoan
+        Ok(())
+    }
+
+    /// Repay a loan and take back the collateral. Like every exit, it stays open to
+    /// blocklisted parties; the blocklist only gates new loans.
+    pub fn repay_loan(ctx: Context<RepayLoan>) -> Result<()> {
+        let loan = &ctx.accounts.loan;
+        let clock = Clock::get()?;
+
+        let interest = loan.interest_due(clock.unix_timestamp)?;
+        let repayment = loan
+            .principal
+            .checked_add(interest)
+            .ok_or(MarketplaceError::Overflow)?;
+
+        // Transfer principal plus interest from borrower to lender
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.borrower.to_account_info(),
+                    to: ctx.accounts.lender.to_account_info(),
+                },
+            ),
+            repayment,
+        )?;
+
+        // Return NFT from escrow to borrower
+        let nft_mint_key = ctx.accounts.nft_mint.key();
+        let escrow_seeds: &[&[u8]] = &[
+            b"escrow",
+            ctx.accounts.marketplace.instance_seed(),
+            nft_mint_key.as_ref(),
+            &[ctx.accounts.escrow.bump],
+        ];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    to: ctx.accounts.borrower_token_account.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                &[escrow_seeds],
+            ),
+            1,
+        )?;
+
+        ctx.accounts.marketplace.loan_closed();
+
+        emit!(LoanRepaid {
+            nft_mint: loan.nft_mint,
+            borrower: loan.borrower,
+            lender: loan.lender,
+            principal: loan.principal,
+            interest,
+        });
+
+        // loan is closed via the `close = borrower` constraint on RepayLoan
+        Ok(())
+    }
+
+    /// Take the collateral of a defaulted loan. Open to blocklisted parties, as
+    /// `repay_loan` is.
+    pub fn foreclose_loan(ctx: Context<ForecloseLoan>) -> Result<()> {
+        let loan = &ctx.accounts.loan;
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp >= loan.due_time,
+            MarketplaceError::LoanNotDefaulted
+        );
+
+        // Transfer the collateral NFT from escrow to lender
+        let nft_mint_key = ctx.accounts.nft_mint.key();
+        let escrow_seeds: &[&[u8]] = &[
+            b"escrow",
+            ctx.accounts.marketplace.instance_seed(),
+            nft_mint_key.as_ref(),
+            &[ctx.accounts.escrow.bump],
+        ];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    to: ctx.accounts.lender_token_account.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                &[escrow_seeds],
+            ),
+            1,
+        )?;
+
+        ctx.accounts.marketplace.loan_closed();
+
+        emit!(LoanForeclosed {
+            nft_mint: loan.nft_mint,
+            borrower: loan.borrower,
+            lender: loan.lender,
+            principal: loan.principal,
+        });
+
+        // loan is closed via the `close = borrower` constraint; the borrower paid its rent
+        Ok(())
+    }
+
+    /// Permanently stop new activity so the program can be sunset or migrated. Escrowed
+    /// NFTs, offer SOL and lent principal are then handed back by `return_listing`,
+    /// `refund_offer`, `refund_bidding_pool` and `return_loan_offer`, which anyone can call;
+    /// loans still run to repayment or foreclosure.
+    pub fn begin_wind_down(ctx: Context<BeginWindDown>) -> Result<()> {
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.require_migrated()?;
+        require!(
+            ctx.accounts.admin.key() == marketplace.admin,
+            MarketplaceError::Unauthorized
+        );
+        require!(
+            !marketplace.winding_down,
+            MarketplaceError::MarketplaceWindingDown
+        );
+        marketplace.winding_down = true;
+
+        let report = &mut ctx.accounts.wind_down_report;
+        report.started_at = Clock::get()?.unix_timestamp;
+        report.listings_returned = 0;
+        report.offers_refunded = 0;
+        report.pools_refunded = 0;
+        report.loan_offers_returned = 0;
+        report.lamports_refunded = 0;
+        report.completed_at = None;
+        report.bump = ctx.bumps.wind_down_report;
+        report.sync(marketplace);
+
+        emit!(WindDownStarted {
+            listings_outstanding: report.listings_outstanding,
+            offers_outstanding: report.offers_outstanding,
+            pools_outstanding: report.pools_outstanding,
+            settlements_outstanding: report.settlements_outstanding,
+            loan_offers_outstanding: report.loan_offers_outstanding,
+            loans_outstanding: report.loans_outstanding,
+        });
+
+        Ok(())
+    }
+
+    /// Return an active listing's NFT to its seller during a wind-down. Permissionless;
+    /// the caller pays for the seller's token account if it was closed. In-place listings
+    /// also need the escrow token account, which the caller pays for and gets back, to
+    /// clear the escrow's delegation.
+    pub fn return_listing(ctx: Context<ReturnListing>) -> Result<()> {
+        require!(
+            ctx.accounts.marketplace.winding_down,
+            MarketplaceError::NotWindingDown
+        );
+        let listing = &ctx.accounts.listing;
+        require!(listing.is_active, MarketplaceError::ListingNotActive);
+
+        let nft_mint_key = ctx.accounts.nft_mint.key();
+        let escrow_seeds: &[&[u8]] = &[
+            b"escrow",
+            ctx.accounts.marketplace.instance_seed(),
+            nft_mint_key.as_ref(),
+            &[ctx.accounts.escrow.bump],
+        ];
+
+        let custody = ctx.accounts.custody();
+        if listing.is_custodial {
+            custody.release(
+                true,
+                ctx.accounts.seller_token_account.as_ref(),
+                escrow_seeds,
+            )?;
+            custody.close(true, ctx.accounts.seller.as_ref(), escrow_seeds)?;
+        } else {
+            custody.thaw(escrow_seeds)?;
+            custody.clear_delegate(ctx.accounts.payer.as_ref(), escrow_seeds)?;
+        }
+
+        emit!(ListingReturned {
+            nft_mint: listing.nft_mint,
+            seller: listing.seller,
+        });
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.listing_closed(listing);
+        let report = &mut ctx.accounts.wind_down_report;
+        report.listings_returned = report
+            .listings_returned
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        report.sync(marketplace);
+
+        // listing and escrow are closed via the `close = seller` constraints on ReturnListing
+        Ok(())
+    }
+
+    /// Refund an open offer's escrowed SOL to its offerer during a wind-down. Pool-backed
+    /// offers hold no SOL and are just closed. Permissionless.
+    pub fn refund_offer(ctx: Context<RefundOffer>) -> Result<()> {
+        require!(
+            ctx.accounts.marketplace.winding_down,
+            MarketplaceError::NotWindingDown
+        );
+        let offer = &ctx.accounts.offer;
+        require!(offer.is_active, MarketplaceError::OfferNotActive);
+        require!(
+            offer.pool_backed || ctx.accounts.offer_escrow.is_some(),
+            MarketplaceError::MissingOfferFunding
+        );
+
+        let amount = if offer.pool_backed { 0 } else { offer.amount };
+        emit!(OfferRefunded {
+            nft_mint: offer.nft_mint,
+            offerer: offer.offerer,
+            amount,
+        });
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.offer_closed(offer);
+        let report = &mut ctx.accounts.wind_down_report;
+        report.offers_refunded = report
+            .offers_refunded
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        report.lamports_refunded = report
+            .lamports_refunded
+            .checked_add(amount)
+            .ok_or(MarketplaceError::Overflow)?;
+        report.sync(marketplace);
+
+        // offer and offer_escrow, with the SOL it holds, are closed to the offerer via the
+        // close constraints on RefundOffer
+        Ok(())
+    }
+
+    /// Return a bidding pool's balance to its owner during a wind-down. Permissionless.
+    pub fn refund_bidding_pool(ctx: Context<RefundBiddingPool>) -> Result<()> {
+        require!(
+            ctx.accounts.marketplace.winding_down,
+            MarketplaceError::NotWindingDown
+        );
+        let amount = ctx.accounts.bidding_pool.balance;
+
+        emit!(BiddingPoolClosed {
+            owner: ctx.accounts.owner.key(),
+            amount,
+        });
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.bidding_pools_closed(1);
+        let report = &mut ctx.accounts.wind_down_report;
+        report.pools_refunded = report
+            .pools_refunded
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        report.lamports_refunded = report
+            .lamports_refunded
+            .checked_add(amount)
+            .ok_or(MarketplaceError::Overflow)?;
+        report.sync(marketplace);
+
+        // bidding_pool, with its balance, is closed to the owner via the `close = owner`
+        // constraint on RefundBiddingPool
+        Ok(())
+    }
+
+    /// Return a loan offer's escrowed principal to its lender during a wind-down.
+    /// Permissionless.
+    pub fn return_loan_offer(ctx: Context<ReturnLoanOffer>) -> Result<()> {
+        require!(
+            ctx.accounts.marketplace.winding_down,
+            MarketplaceError::NotWindingDown
+        );
+        let loan_offer = &ctx.accounts.loan_offer;
+        let principal = loan_offer.principal;
+
+        emit!(LoanOfferReturned {
+            lender: loan_offer.lender,
+            collection: loan_offer.collection,
+            principal,
+        });
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.loan_offer_closed();
+        let report = &mut ctx.accounts.wind_down_report;
+        report.loan_offers_returned = report
+            .loan_offers_returned
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        report.lamports_refunded = report
+            .lamports_refunded
+            .checked_add(principal)
+            .ok_or(MarketplaceError::Overflow)?;
+        report.sync(marketplace);
+
+        // loan_offer, with the principal it holds, is closed to the lender via the
+        // `close = lender` constraint on ReturnLoanOffer
+        Ok(())
+    }
+
+    /// Stamp the wind-down report once no listing, offer, bidding pool, settlement, loan
+    /// offer or loan escrow remains. Legacy listings and offers only count once migrated, so migrate
+    /// them before relying on the stamp. Permissionless.
+    pub fn complete_wind_down(ctx: Context<CompleteWindDown>) -> Result<()> {
+        let marketplace = &ctx.accounts.marketplace;
+        require!(marketplace.winding_down, MarketplaceError::NotWindingDown);
+        require!(
+            marketplace.active_listings == 0
+                && marketplace.open_offers == 0
+                && marketplace.bidding_pools == 0
+                && marketplace.open_settlements == 0
+                && marketplace.open_loan_offers == 0
+                && marketplace.active_loans == 0,
+            MarketplaceError::EscrowOutstanding
+        );
+
+        let report = &mut ctx.accounts.wind_down_report;
+        report.sync(marketplace);
+        if report.completed_at.is_none() {
+            report.completed_at = Some(Clock::get()?.unix_timestamp);
+        }
+
+        emit!(WindDownCompleted {
+            listings_returned: report.listings_returned,
+            offers_refunded: report.offers_refunded,
+            pools_refunded: report.pools_refunded,
+            loan_offers_returned: report.loan_offers_returned,
+            lamports_refunded: report.lamports_refunded,
+        });
+
+        Ok(())
+    }
+
+    /// Realloc a legacy marketplace account into the current layout. Permissionless;
+    /// the payer funds the extra rent.
+    pub fn migrate_marketplace(ctx: Context<MigrateMarketplace>) -> Result<()> {
+        let marketplace = &mut ctx.accounts.marketplace;
+        let key = marketplace.key();
+        upgrade_version(&mut marketplace.version, Marketplace::VERSION, key);
+        Ok(())
+    }
+
+    /// Realloc a legacy listing into the current layout, counting it in
+    /// `active_listings` if still active. Permissionless; the marketplace must be
+    /// migrated first.
+    pub fn migrate_listing(ctx: Context<MigrateListing>) -> Result<()> {
+        ctx.accounts.marketplace.require_migrated()?;
+        let listing = &mut ctx.accounts.listing;
+        if listing.is_active && !listing.counted {
+            // It already counts towards `listing_count`
+            let marketplace = &mut ctx.accounts.marketplace;
+            marketplace.active_listings = marketplace
+                .active_listings
+                .checked_add(1)
+                .ok_or(MarketplaceError::Overflow)?;
+            listing.counted = true;
+        }
+        let key = listing.key();
+        upgrade_version(&mut listing.version, Listing::VERSION, key);
+        Ok(())
+    }
+
+    /// Realloc a legacy offer into the current layout, counting it in `open_offers` if
+    /// still active. Permissionless; the marketplace must be migrated first.
+    pub fn migrate_offer(ctx: Context<MigrateOffer>) -> Result<()> {
+        ctx.accounts.marketplace.require_migrated()?;
+        let offer = &mut ctx.accounts.offer;
+        if offer.is_active && !offer.counted {
+            ctx.accounts.marketplace.offer_opened()?;
+            offer.counted = true;
+        }
+        let key = offer.key();
+        upgrade_version(&mut offer.version, Offer::VERSION, key);
+        Ok(())
+    }
+}
+
+// ─── Account Contexts ────────────────────────────────────────────────────────
+
+#[derive(Accounts)]
+pub struct InitializeMarketplace<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Marketplace::INIT_SPACE,
+        seeds = [b"marketplace"],
+        bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Fee recipient, validated by admin
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(instance: Pubkey)]
+pub struct InitializeInstance<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// The default instance's admin, who approves partner storefronts
+    pub platform_admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref()],
+        bump = platform.bump,
+        constraint = platform.admin == platform_admin.key() @ MarketplaceError::Unauthorized,
+    )]
+    pub platform: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Marketplace::INIT_SPACE,
+        seeds = [b"marketplace".as_ref(), instance.as_ref()],
+        bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Fee recipient, validated by admin
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ListNft<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Blocklist PDA of nft_mint; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()], bump)]
+    pub mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the seller; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), seller.key().as_ref()], bump)]
+    pub seller_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Collection config PDA of the NFT's verified collection — required whenever it has one, even if no config exists; checked in the handler
+    pub collection_config: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex metadata PDA of nft_mint, validated in the handler
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// Listing PDA — closed by every sale and cancellation, so relisting inits it afresh;
+    /// init_if_needed also reopens inactive listings left by older sales, growing any still
+    /// in the legacy layout
+    #[account(
+        init_if_needed,
+        payer = seller,
+        space = reopen_space(listing, 8 + Listing::INIT_SPACE),
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// Escrow authority PDA — closed with the listing; init_if_needed since a loan or an
+    /// older sale may have left it open
+    #[account(
+        init_if_needed,
+        payer = seller,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = seller,
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = seller,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ListNftInPlace<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Blocklist PDA of nft_mint; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()], bump)]
+    pub mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the seller; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), seller.key().as_ref()], bump)]
+    pub seller_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Collection config PDA of the NFT's verified collection — required whenever it has one, even if no config exists; checked in the handler
+    pub collection_config: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex metadata PDA of nft_mint, validated in the handler
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Metaplex edition PDA — must be the mint's freeze authority, checked in the handler
+    pub nft_edition: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = seller,
+        space = reopen_space(listing, 8 + Listing::INIT_SPACE),
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// Escrow authority PDA — acts as the token delegate instead of holding the NFT
+    #[account(
+        init_if_needed,
+        payer = seller,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = seller,
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Metaplex token metadata program
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ListNftInPlace<'info> {
+    fn custody(&self) -> ListingCustody<'_, 'info> {
+        ListingCustody {
+            nft_mint: self.nft_mint.as_ref(),
+            escrow: self.escrow.as_ref(),
+            escrow_token_account: None,
+            seller_token_account: Some(self.seller_token_account.as_ref()),
+            nft_edition: Some(self.nft_edition.as_ref()),
+            token_metadata_program: Some(self.token_metadata_program.as_ref()),
+            token_program: self.token_program.as_ref(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct CancelListing<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Seller receives the listing and escrow rent — validated against listing.seller
+    #[account(mut, constraint = seller.key() == listing.seller @ MarketplaceError::InvalidSeller)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+        close = seller,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = escrow.bump,
+        close = seller,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    /// Required for custodial listings, and for a moderator cancelling an in-place listing
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Option<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = listing.seller,
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Metaplex edition PDA — required for non-custodial listings, validated by the metadata program
+    pub nft_edition: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex token metadata program — required for non-custodial listings
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> CancelListing<'info> {
+    fn custody(&self) -> ListingCustody<'_, 'info> {
+        ListingCustody {
+            nft_mint: self.nft_mint.as_ref(),
+            escrow: self.escrow.as_ref(),
+            escrow_token_account: self.escrow_token_account.as_ref().map(|a| a.as_ref()),
+            seller_token_account: Some(self.seller_token_account.as_ref()),
+            nft_edition: self.nft_edition.as_ref().map(|a| a.as_ref()),
+            token_metadata_program: self.token_metadata_program.as_ref().map(|a| a.as_ref()),
+            token_program: self.token_program.as_ref(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct BuyNft<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Seller receives SOL payment — validated against listing.seller
+    #[account(mut, constraint = seller.key() == listing.seller @ MarketplaceError::InvalidSeller)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Fee recipient — validated against marketplace.fee_recipient
+    #[account(mut, constraint = fee_recipient.key() == marketplace.fee_recipient @ MarketplaceError::InvalidFeeRecipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    /// CHECK: Fee tier table PDA; uninitialised until the admin sets tiers, read in the handler
+    #[account(seeds = [b"fee_tiers".as_ref(), marketplace.instance_seed()], bump)]
+    pub fee_tiers: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer".as_ref(), marketplace.instance_seed(), referrer.wallet.as_ref()],
+        bump = referrer.bump,
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+
+    /// CHECK: Referrer payout wallet — validated against referrer.wallet
+    #[account(mut)]
+    pub referrer_wallet: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Blocklist PDA of referrer_wallet, required with it; address and entry are checked in the handler
+    pub referrer_blocklist: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's stats PDA, which selects their fee tier; usually uninitialised until opened, read and updated in the handler
+    #[account(mut, seeds = [b"seller_stats".as_ref(), marketplace.instance_seed(), listing.seller.as_ref()], bump)]
+    pub seller_stats: UncheckedAccount<'info>,
+
+    /// The consignment agent's anft_did profile — required when the listing's agent is a DID
+    pub agent_did: Option<Account<'info, DidProfile>>,
+
+    /// CHECK: Consignment agent's wallet — required when the listing has an agent, validated in the handler
+    #[account(mut)]
+    pub agent_wallet: Option<UncheckedAccount<'info>>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Blocklist PDA of nft_mint; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()], bump)]
+    pub mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the buyer; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), buyer.key().as_ref()], bump)]
+    pub buyer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the seller; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), seller.key().as_ref()], bump)]
+    pub seller_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of nft_mint, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), nft_mint.key().as_ref()], bump)]
+    pub platform_mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of the buyer, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), buyer.key().as_ref()], bump)]
+    pub platform_buyer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of the seller, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), seller.key().as_ref()], bump)]
+    pub platform_seller_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Collection config PDA of the listing's collection — required whenever it has one, even if no config exists; checked in the handler
+    pub collection_config: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex metadata PDA of nft_mint, validated in the handler
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Revenue split PDA of nft_mint; usually uninitialised, consumed in the handler
+    #[account(mut, seeds = [b"revenue_split".as_ref(), nft_mint.key().as_ref()], bump)]
+    pub revenue_split: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+        close = seller,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = escrow.bump,
+        close = seller,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    /// Required for custodial listings only
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Required for non-custodial listings only
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = seller,
+    )]
+    pub seller_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Metaplex edition PDA — required for non-custodial listings, validated by the metadata program
+    pub nft_edition: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex token metadata program — required for non-custodial listings
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = nft_mint,
+        associated_token::authority = buyer,
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+
+    /// Holds the seller's proceeds; required exactly when the listing has a hold period
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + Settlement::INIT_SPACE,
+        seeds = [b"settlement".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), buyer.key().as_ref()],
+        bump,
+    )]
+    pub settlement: Option<Account<'info, Settlement>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> BuyNft<'info> {
+    fn custody(&self) -> ListingCustody<'_, 'info> {
+        ListingCustody {
+            nft_mint: self.nft_mint.as_ref(),
+            escrow: self.escrow.as_ref(),
+            escrow_token_account: self.escrow_token_account.as_ref().map(|a| a.as_ref()),
+            seller_token_account: self.seller_token_account.as_ref().map(|a| a.as_ref()),
+            nft_edition: self.nft_edition.as_ref().map(|a| a.as_ref()),
+            token_metadata_program: self.token_metadata_program.as_ref().map(|a| a.as_ref()),
+            token_program: self.token_program.as_ref(),
+        }
+    }
+
+    fn sale_fees(&self) -> Result<SaleFees<'_>> {
+        Ok(SaleFees {
+            marketplace: &self.marketplace,
+            fee_tiers: FeeTierTable::load(&self.fee_tiers)?,
+            seller_stats: SellerStats::load(&self.seller_stats)?,
+            referrer: self.referrer.as_deref(),
+        })
+    }
+}
+
+#[derive(Accounts)]
+pub struct OpenDispute<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement".as_ref(), marketplace.instance_seed(), settlement.nft_mint.as_ref(), buyer.key().as_ref()],
+        bump = settlement.bump,
+    )]
+    pub settlement: Account<'info, Settlement>,
+
+    #[account(address = settlement.nft_mint @ MarketplaceError::SettlementMismatch)]
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = buyer,
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+
+    /// Holds the returned NFT until the dispute is resolved
+    #[account(
+        init,
+        payer = buyer,
+        associated_token::mint = nft_mint,
+        associated_token::authority = settlement,
+    )]
+    pub settlement_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseSettlement<'info> {
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Buyer — validated by the settlement seeds
+    pub buyer: UncheckedAccount<'info>,
+
+    /// CHECK: Receives the settlement rent — validated against settlement.rent_payer
+    #[account(mut, constraint = rent_payer.key() == settlement.rent_payer @ MarketplaceError::SettlementMismatch)]
+    pub rent_payer: UncheckedAccount<'info>,
+
+    /// CHECK: Seller receives proceeds not owed to a split — validated against settlement.seller
+    #[account(mut, constraint = seller.key() == settlement.seller @ MarketplaceError::InvalidSeller)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement".as_ref(), marketplace.instance_seed(), settlement.nft_mint.as_ref(), buyer.key().as_ref()],
+        bump = settlement.bump,
+        close = rent_payer,
+    )]
+    pub settlement: Account<'info, Settlement>,
+
+    /// Required when the held proceeds are owed to a revenue split
+    #[account(seeds = [b"revenue_split".as_ref(), settlement.nft_mint.as_ref()], bump = revenue_split.bump)]
+    pub revenue_split: Option<Account<'info, RevenueSplit>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Buyer receives any refund and the returned NFT's account rent — validated by the settlement seeds
+    #[account(mut)]
+    pub buyer: UncheckedAccount<'info>,
+
+    /// CHECK: Receives the settlement rent — validated against settlement.rent_payer
+    #[account(mut, constraint = rent_payer.key() == settlement.rent_payer @ MarketplaceError::SettlementMismatch)]
+    pub rent_payer: UncheckedAccount<'info>,
+
+    /// CHECK: Seller receives released proceeds not owed to a split — validated against settlement.seller
+    #[account(mut, constraint = seller.key() == settlement.seller @ MarketplaceError::InvalidSeller)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement".as_ref(), marketplace.instance_seed(), settlement.nft_mint.as_ref(), buyer.key().as_ref()],
+        bump = settlement.bump,
+        close = rent_payer,
+    )]
+    pub settlement: Account<'info, Settlement>,
+
+    /// Required when the held proceeds are owed to a revenue split
+    #[account(seeds = [b"revenue_split".as_ref(), settlement.nft_mint.as_ref()], bump = revenue_split.bump)]
+    pub revenue_split: Option<Account<'info, RevenueSplit>>,
+
+    #[account(address = settlement.nft_mint @ MarketplaceError::SettlementMismatch)]
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = settlement,
+    )]
+    pub settlement_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Receives the NFT — the seller's associated token account when it is returned to the seller, else the buyer's; created if needed, the associated token program verifies the address
+    #[account(mut)]
+    pub nft_recipient_token_account: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BuyMany<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Blocklist PDA of the buyer; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), buyer.key().as_ref()], bump)]
+    pub buyer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of the buyer, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), buyer.key().as_ref()], bump)]
+    pub platform_buyer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Fee recipient — validated against marketplace.fee_recipient
+    #[account(mut, constraint = fee_recipient.key() == marketplace.fee_recipient @ MarketplaceError::InvalidFeeRecipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    /// CHECK: Fee tier table PDA; uninitialised until the admin sets tiers, read in the handler
+    #[account(seeds = [b"fee_tiers".as_ref(), marketplace.instance_seed()], bump)]
+    pub fee_tiers: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer".as_ref(), marketplace.instance_seed(), referrer.wallet.as_ref()],
+        bump = referrer.bump,
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+
+    /// CHECK: Referrer payout wallet — validated against referrer.wallet
+    #[account(mut)]
+    pub referrer_wallet: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Blocklist PDA of referrer_wallet, required with it; address and entry are checked in the handler
+    pub referrer_blocklist: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex token metadata program — required if any listing is non-custodial
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MakeOffer<'info> {
+    #[account(mut)]
+    pub offerer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Blocklist PDA of nft_mint; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()], bump)]
+    pub mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the offerer; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), offerer.key().as_ref()], bump)]
+    pub offerer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of nft_mint, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), nft_mint.key().as_ref()], bump)]
+    pub platform_mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of the offerer, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), offerer.key().as_ref()], bump)]
+    pub platform_offerer_blocklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    #[account(
+        init_if_needed,
+        payer = offerer,
+        space = reopen_space(offer, 8 + Offer::INIT_SPACE),
+        seeds = [b"offer".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = offerer,
+        space = 8 + OfferEscrow::INIT_SPACE,
+        seeds = [b"offer_escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump,
+    )]
+    pub offer_escrow: Account<'info, OfferEscrow>,
+
+    // The accounts below are needed only when the offer meets the auto-accept price
+    /// CHECK: Seller receives SOL payment — validated against listing.seller in the handler
+    #[account(mut)]
+    pub seller: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Blocklist PDA of the seller; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), listing.seller.as_ref()], bump)]
+    pub seller_blocklist: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Default instance's blocklist PDA of the seller, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), listing.seller.as_ref()], bump)]
+    pub platform_seller_blocklist: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fee recipient — validated against marketplace.fee_recipient
+    #[account(mut, constraint = fee_recipient.key() == marketplace.fee_recipient @ MarketplaceError::InvalidFeeRecipient)]
+    pub fee_recipient: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fee tier table PDA; uninitialised until the admin sets tiers, read in the handler
+    #[account(seeds = [b"fee_tiers".as_ref(), marketplace.instance_seed()], bump)]
+    pub fee_tiers: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer".as_ref(), marketplace.instance_seed(), referrer.wallet.as_ref()],
+        bump = referrer.bump,
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+
+    /// CHECK: Referrer payout wallet — validated against referrer.wallet
+    #[account(mut)]
+    pub referrer_wallet: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Blocklist PDA of referrer_wallet, required with it; address and entry are checked in the handler
+    pub referrer_blocklist: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's stats PDA, which selects their fee tier; usually uninitialised until opened, read and updated in the handler
+    #[account(mut, seeds = [b"seller_stats".as_ref(), marketplace.instance_seed(), listing.seller.as_ref()], bump)]
+    pub seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// The consignment agent's anft_did profile — required when the listing's agent is a DID
+    pub agent_did: Option<Account<'info, DidProfile>>,
+
+    /// CHECK: Consignment agent's wallet — required when the listing has an agent, validated in the handler
+    #[account(mut)]
+    pub agent_wallet: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Collection config PDA of the listing's collection — required whenever it has one, even if no config exists; checked in the handler
+    pub collection_config: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex metadata PDA of nft_mint, validated in the handler
+    pub nft_metadata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Revenue split PDA of nft_mint; usually uninitialised, consumed in the handler
+    #[account(mut, seeds = [b"revenue_split".as_ref(), nft_mint.key().as_ref()], bump)]
+    pub revenue_split: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = escrow.bump,
+    )]
+    pub escrow: Option<Account<'info, Escrow>>,
+
+    /// Required for custodial listings only
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Required for non-custodial listings only
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = seller,
+    )]
+    pub seller_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Metaplex edition PDA — required for non-custodial listings, validated by the metadata program
+    pub nft_edition: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex token metadata program — required for non-custodial listings
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Offerer's NFT account — created if needed, the associated token program verifies the address
+    #[account(mut)]
+    pub offerer_token_account: Option<UncheckedAccount<'info>>,
+
+    /// Holds the seller's proceeds; required exactly when the listing has a hold period
+    #[account(
+        init,
+        payer = offerer,
+        space = 8 + Settlement::INIT_SPACE,
+        seeds = [b"settlement".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump,
+    )]
+    pub settlement: Option<Account<'info, Settlement>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> MakeOffer<'info> {
+    /// Fill the listing at `amount` as `accept_offer` would, paid straight from the offerer.
+    /// The offer accounts `make_offer` opened, or an earlier offer they held, are closed
+    /// back to the offerer.
+    fn settle(
+        &mut self,
+        amount: u64,
+        settlement_bump: Option<u8>,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let missing = || error!(MarketplaceError::MissingSettlementAccount);
+        let seller = self.seller.as_ref().ok_or_else(missing)?;
+        let seller_blocklist = self.seller_blocklist.as_ref().ok_or_else(missing)?;
+        let platform_seller_blocklist = self
+            .platform_seller_blocklist
+            .as_ref()
+            .ok_or_else(missing)?;
+        let fee_recipient = self.fee_recipient.as_ref().ok_or_else(missing)?;
+        let escrow = self.escrow.as_ref().ok_or_else(missing)?;
+        let revenue_split = self.revenue_split.as_ref().ok_or_else(missing)?;
+        let offerer_token_account = self.offerer_token_account.as_ref().ok_or_else(missing)?;
+        let token_program = self.token_program.as_ref().ok_or_else(missing)?;
+        let associated_token_program =
+            self.associated_token_program.as_ref().ok_or_else(missing)?;
+
+        let listing = &self.listing;
+        require_keys_eq!(
+            seller.key(),
+            listing.seller,
+            MarketplaceError::InvalidSeller
+        );
+        BlocklistEntry::require_clear(seller_blocklist, &listing.seller)?;
+        BlocklistEntry::require_clear(platform_seller_blocklist, &listing.seller)?;
+
+        let collection = CollectionMatch::for_sale(
+            listing,
+            self.collection_config.as_deref(),
+            self.nft_metadata.as_ref().ok_or_else(missing)?,
+            self.marketplace.instance_seed(),
+        )?;
+        let seller_stats = self.seller_stats.as_ref().ok_or_else(missing)?;
+        let mut payout = SaleFees {
+            marketplace: &self.marketplace,
+            fee_tiers: FeeTierTable::load(&self.fee_tiers)?,
+            seller_stats: SellerStats::load(seller_stats)?,
+            referrer: self.referrer.as_deref(),
+        }
+        .payout(amount, collection.as_ref())?;
+        listing.charge_commission(&mut payout, amount, self.agent_did.as_ref())?;
+        require!(
+            (listing.hold_period > 0) == self.settlement.is_some(),
+            MarketplaceError::SettlementMismatch
+        );
+        let held_split =
+            payout.apply_split(revenue_split, &listing.seller, self.settlement.is_some())?;
+        let fee = payout.total_fee();
+
+        let offerer = self.offerer.to_account_info();
+        let (creators, split_recipients) = payout.partition_accounts(remaining_accounts);
+        let proceeds_to = match &self.settlement {
+            Some(settlement) => settlement.to_account_info(),
+            None => seller.to_account_info(),
+        };
+        SalePayees {
+            seller: &proceeds_to,
+            fee_recipient,
+            referrer: self.referrer.as_mut(),
+            referrer_wallet: self.referrer_wallet.as_deref(),
+            referrer_blocklist: self.referrer_blocklist.as_deref(),
+            instance_seed: self.marketplace.instance_seed(),
+            creators,
+            split_recipients,
+            agent_wallet: self.agent_wallet.as_deref(),
+            system_program: self.system_program.as_ref(),
+        }
+        .pay(&payout, &offerer, listing.nft_mint)?;
+        SellerStats::record_sale(seller_stats, payout.marketplace_fee)?;
+
+        anchor_spl::associated_token::create_idempotent(CpiContext::new(
+            associated_token_program.to_account_info(),
+            anchor_spl::associated_token::Create {
+                payer: offerer.clone(),
+                associated_token: offerer_token_account.to_account_info(),
+                authority: offerer.clone(),
+                mint: self.nft_mint.to_account_info(),
+                system_program: self.system_program.to_account_info(),
+                token_program: token_program.to_account_info(),
+            },
+        ))?;
+
+        let nft_mint_key = self.nft_mint.key();
+        let escrow_seeds: &[&[u8]] = &[
+            b"escrow",
+            self.marketplace.instance_seed(),
+            nft_mint_key.as_ref(),
+            &[escrow.bump],
+        ];
+        let custody = ListingCustody {
+            nft_mint: self.nft_mint.as_ref(),
+            escrow: escrow.as_ref(),
+            escrow_token_account: self.escrow_token_account.as_ref().map(|a| a.as_ref()),
+            seller_token_account: self.seller_token_account.as_ref().map(|a| a.as_ref()),
+            nft_edition: self.nft_edition.as_ref().map(|a| a.as_ref()),
+            token_metadata_program: self.token_metadata_program.as_ref().map(|a| a.as_ref()),
+            token_program: token_program.as_ref(),
+        };
+        custody.release(listing.is_custodial, offerer_token_account, escrow_seeds)?;
+        custody.close(listing.is_custodial, seller, escrow_seeds)?;
+
+        if let Some(settlement) = self.settlement.as_mut() {
+            let offerer = offerer.key();
+            settlement.hold(
+                listing,
+                offerer,
+                offerer,
+                payout.seller_amount,
+                held_split,
+                settlement_bump,
+            )?;
+            self.marketplace.settlement_opened()?;
+        }
+
+        emit!(OfferAccepted {
+            nft_mint: nft_mint_key,
+            buyer: offerer.key(),
+            seller: listing.seller,
+            price: amount,
+            fee,
+        });
+
+        self.marketplace.listing_closed(listing);
+        if self.offer.is_active {
+            self.marketplace.offer_closed(&self.offer);
+        }
+        self.listing.close(seller.to_account_info())?;
+        escrow.close(seller.to_account_info())?;
+        self.offer.close(offerer.clone())?;
+        self.offer_escrow.close(offerer)
+    }
+}
+
+#[derive(Accounts)]
+pub struct MakePoolOffer<'info> {
+    #[account(mut)]
+    pub offerer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Blocklist PDA of nft_mint; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()], bump)]
+    pub mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the offerer; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), offerer.key().as_ref()], bump)]
+    pub offerer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of nft_mint, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), nft_mint.key().as_ref()], bump)]
+    pub platform_mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of the offerer, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), offerer.key().as_ref()], bump)]
+    pub platform_offerer_blocklist: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    #[account(
+        seeds = [b"bidding_pool".as_ref(), marketplace.instance_seed(), offerer.key().as_ref()],
+        bump = bidding_pool.bump,
+    )]
+    pub bidding_pool: Account<'info, BiddingPool>,
+
+    #[account(
+        init_if_needed,
+        payer = offerer,
+        space = reopen_space(offer, 8 + Offer::INIT_SPACE),
+        seeds = [b"offer".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOffer<'info> {
+    #[account(mut)]
+    pub offerer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"offer".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump = offer.bump,
+        close = offerer,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// Absent for pool-backed offers
+    #[account(
+        mut,
+        seeds = [b"offer_escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump = offer_escrow.bump,
+        close = offerer,
+    )]
+    pub offer_escrow: Option<Account<'info, OfferEscrow>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToPool<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + BiddingPool::INIT_SPACE,
+        seeds = [b"bidding_pool".as_ref(), marketplace.instance_seed(), owner.key().as_ref()],
+        bump,
+    )]
+    pub bidding_pool: Account<'info, BiddingPool>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromPool<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"bidding_pool".as_ref(), marketplace.instance_seed(), owner.key().as_ref()],
+        bump = bidding_pool.bump,
+    )]
+    pub bidding_pool: Account<'info, BiddingPool>,
+}
+
+#[derive(Accounts)]
+pub struct CloseBiddingPool<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"bidding_pool".as_ref(), marketplace.instance_seed(), owner.key().as_ref()],
+        bump = bidding_pool.bump,
+        close = owner,
+    )]
+    pub bidding_pool: Account<'info, BiddingPool>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOffer<'info> {
+    /// Seller, or the listing's consignment agent
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Seller receives the proceeds and listing rent — validated against listing.seller
+    #[account(mut, constraint = seller.key() == listing.seller @ MarketplaceError::InvalidSeller)]
+    pub seller: UncheckedAccount<'info>,
+
+    /// CHECK: Offerer (buyer) receives the offer rent — validated against offer.offerer
+    #[account(mut)]
+    pub offerer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Fee recipient — validated against marketplace.fee_recipient
+    #[account(mut, constraint = fee_recipient.key() == marketplace.fee_recipient @ MarketplaceError::InvalidFeeRecipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    /// CHECK: Fee tier table PDA; uninitialised until the admin sets tiers, read in the handler
+    #[account(seeds = [b"fee_tiers".as_ref(), marketplace.instance_seed()], bump)]
+    pub fee_tiers: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer".as_ref(), marketplace.instance_seed(), referrer.wallet.as_ref()],
+        bump = referrer.bump,
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+
+    /// CHECK: Referrer payout wallet — validated against referrer.wallet
+    #[account(mut)]
+    pub referrer_wallet: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Blocklist PDA of referrer_wallet, required with it; address and entry are checked in the handler
+    pub referrer_blocklist: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's stats PDA, which selects their fee tier; usually uninitialised until opened, read and updated in the handler
+    #[account(mut, seeds = [b"seller_stats".as_ref(), marketplace.instance_seed(), listing.seller.as_ref()], bump)]
+    pub seller_stats: UncheckedAccount<'info>,
+
+    /// The consignment agent's anft_did profile — required when the listing's agent is a DID
+    pub agent_did: Option<Account<'info, DidProfile>>,
+
+    /// CHECK: Consignment agent's wallet — required when the listing has an agent, validated in the handler
+    #[account(mut)]
+    pub agent_wallet: Option<UncheckedAccount<'info>>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Blocklist PDA of nft_mint; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()], bump)]
+    pub mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the offerer; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), offerer.key().as_ref()], bump)]
+    pub offerer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the seller; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), seller.key().as_ref()], bump)]
+    pub seller_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of nft_mint, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), nft_mint.key().as_ref()], bump)]
+    pub platform_mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of the offerer, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), offerer.key().as_ref()], bump)]
+    pub platform_offerer_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Default instance's blocklist PDA of the seller, which applies on every instance; checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), seller.key().as_ref()], bump)]
+    pub platform_seller_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Collection config PDA of the listing's collection — required whenever it has one, even if no config exists; checked in the handler
+    pub collection_config: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex metadata PDA of nft_mint, validated in the handler
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Revenue split PDA of nft_mint; usually uninitialised, consumed in the handler
+    #[account(mut, seeds = [b"revenue_split".as_ref(), nft_mint.key().as_ref()], bump)]
+    pub revenue_split: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+        close = seller,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = escrow.bump,
+        close = seller,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    /// Required for custodial listings only
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Required for non-custodial listings only
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = seller,
+    )]
+    pub seller_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Metaplex edition PDA — required for non-custodial listings, validated by the metadata program
+    pub nft_edition: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex token metadata program — required for non-custodial listings
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = nft_mint,
+        associated_token::authority = offerer,
+    )]
+    pub offerer_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"offer".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump = offer.bump,
+        close = offerer,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// Absent for pool-backed offers
+    #[account(
+        mut,
+        seeds = [b"offer_escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump = offer_escrow.bump,
+        close = offerer,
+    )]
+    pub offer_escrow: Option<Account<'info, OfferEscrow>>,
+
+    /// The offerer's bidding pool — required for pool-backed offers only
+    #[account(
+        mut,
+        seeds = [b"bidding_pool".as_ref(), marketplace.instance_seed(), offerer.key().as_ref()],
+        bump = bidding_pool.bump,
+    )]
+    pub bidding_pool: Option<Account<'info, BiddingPool>>,
+
+    /// Holds the seller's proceeds; required exactly when the listing has a hold period
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Settlement::INIT_SPACE,
+        seeds = [b"settlement".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref(), offerer.key().as_ref()],
+        bump,
+    )]
+    pub settlement: Option<Account<'info, Settlement>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> AcceptOffer<'info> {
+    fn custody(&self) -> ListingCustody<'_, 'info> {
+        ListingCustody {
+            nft_mint: self.nft_mint.as_ref(),
+            escrow: self.escrow.as_ref(),
+            escrow_token_account: self.escrow_token_account.as_ref().map(|a| a.as_ref()),
+            seller_token_account: self.seller_token_account.as_ref().map(|a| a.as_ref()),
+            nft_edition: self.nft_edition.as_ref().map(|a| a.as_ref()),
+            token_metadata_program: self.token_metadata_program.as_ref().map(|a| a.as_ref()),
+            token_program: self.token_program.as_ref(),
+        }
+    }
+
+    fn sale_fees(&self) -> Result<SaleFees<'_>> {
+        Ok(SaleFees {
+            marketplace: &self.marketplace,
+            fee_tiers: FeeTierTable::load(&self.fee_tiers)?,
+            seller_stats: SellerStats::load(&self.seller_stats)?,
+            referrer: self.referrer.as_deref(),
+        })
+    }
+}
+
+#[derive(Accounts)]
+pub struct UpdatePrice<'info> {
+    /// Seller, or the listing's consignment agent where the instruction allows it
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// The consignment agent's anft_did profile — required when a DID agent signs
+    pub agent_did: Option<Account<'info, DidProfile>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateListing<'info> {
+    /// Seller, or the listing's consignment agent
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// The consignment agent's anft_did profile — required when a DID agent signs
+    pub agent_did: Option<Account<'info, DidProfile>>,
+}
+
+#[derive(Accounts)]
+pub struct ListNftsBatch<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Blocklist PDA of the seller; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), seller.key().as_ref()], bump)]
+    pub seller_blocklist: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelListingsBatch<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Metaplex token metadata program — required if any listing is non-custodial
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetRevenueSplit<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        constraint = nft_mint.mint_authority == COption::Some(authority.key()) @ MarketplaceError::Unauthorized,
+        constraint = nft_mint.supply == 0 @ MarketplaceError::MintAlreadyIssued,
+    )]
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + RevenueSplit::INIT_SPACE,
+        seeds = [b"revenue_split".as_ref(), nft_mint.key().as_ref()],
+        bump,
+    )]
+    pub revenue_split: Account<'info, RevenueSplit>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePricesBatch<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[derive(Accounts)]
+#[instruction(seller: Pubkey)]
+pub struct OpenSellerStats<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + SellerStats::INIT_SPACE,
+        seeds = [b"seller_stats".as_ref(), marketplace.instance_seed(), seller.as_ref()],
+        bump,
+    )]
+    pub seller_stats: Account<'info, SellerStats>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct RegisterReferrer<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Referrer::INIT_SPACE,
+        seeds = [b"referrer".as_ref(), marketplace.instance_seed(), wallet.as_ref()],
+        bump,
+    )]
+    pub referrer: Account<'info, Referrer>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateReferrer<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer".as_ref(), marketplace.instance_seed(), referrer.wallet.as_ref()],
+        bump = referrer.bump,
+    )]
+    pub referrer: Account<'info, Referrer>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveReferrer<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer".as_ref(), marketplace.instance_seed(), referrer.wallet.as_ref()],
+        bump = referrer.bump,
+        close = authority,
+    )]
+    pub referrer: Account<'info, Referrer>,
+}
+
+#[derive(Accounts)]
+#[instruction(key: Pubkey)]
+pub struct FlagBlocklisted<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + BlocklistEntry::INIT_SPACE,
+        seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), key.as_ref()],
+        bump,
+    )]
+    pub blocklist_entry: Account<'info, BlocklistEntry>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnflagBlocklisted<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), blocklist_entry.key.as_ref()],
+        bump = blocklist_entry.bump,
+        close = authority,
+    )]
+    pub blocklist_entry: Account<'info, BlocklistEntry>,
+}
+
+#[derive(Accounts)]
+pub struct TransferAdmin<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    pub new_admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[derive(Accounts)]
+pub struct ManageRole<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[derive(Accounts)]
+pub struct PauseMarketplace<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[derive(Accounts)]
+pub struct UnpauseMarketplace<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[derive(Accounts)]
+#[instruction(change: AdminChange)]
+pub struct QueueAdminChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// One pending change per kind; an existing one must be executed or cancelled first
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingChange::INIT_SPACE,
+        seeds = [b"pending_change".as_ref(), marketplace.instance_seed(), &[change.kind()]],
+        bump,
+    )]
+    pub pending_change: Account<'info, PendingChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteAdminChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"pending_change".as_ref(), marketplace.instance_seed(), &[pending_change.change.kind()]],
+        bump = pending_change.bump,
+        close = authority,
+    )]
+    pub pending_change: Account<'info, PendingChange>,
+
+    /// Required for emergency withdrawals and treasury recipient changes only
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + Treasury::INIT_SPACE,
+        seeds = [b"treasury".as_ref(), marketplace.instance_seed()],
+        bump,
+    )]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    /// Required for fee tier changes only
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + FeeTierTable::INIT_SPACE,
+        seeds = [b"fee_tiers".as_ref(), marketplace.instance_seed()],
+        bump,
+    )]
+    pub fee_tiers: Option<Account<'info, FeeTierTable>>,
+
+    /// Required for collection config changes only
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + CollectionConfig::INIT_SPACE,
+        seeds = [
+            b"collection_config".as_ref(),
+            marketplace.instance_seed(),
+            pending_change.change.collection().as_ref(),
+        ],
+        bump,
+    )]
+    pub collection_config: Option<Account<'info, CollectionConfig>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAdminChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"pending_change".as_ref(), marketplace.instance_seed(), &[pending_change.change.kind()]],
+        bump = pending_change.bump,
+        close = authority,
+    )]
+    pub pending_change: Account<'info, PendingChange>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeFees<'info> {
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury".as_ref(), marketplace.instance_seed()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeTokenFees<'info> {
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        seeds = [b"treasury".as_ref(), marketplace.instance_seed()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+    )]
+    pub treasury_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(collection: Pubkey)]
+pub struct CreateLoanOffer<'info> {
+    #[account(mut)]
+    pub lender: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: Blocklist PDA of the lender; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), lender.key().as_ref()], bump)]
+    pub lender_blocklist: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = lender,
+        space = 8 + LoanOffer::INIT_SPACE,
+        seeds = [b"loan_offer".as_ref(), marketplace.instance_seed(), collection.as_ref(), lender.key().as_ref()],
+        bump,
+    )]
+    pub loan_offer: Account<'info, LoanOffer>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelLoanOffer<'info> {
+    #[account(mut)]
+    pub lender: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"loan_offer".as_ref(), marketplace.instance_seed(), loan_offer.collection.as_ref(), lender.key().as_ref()],
+        bump = loan_offer.bump,
+        close = lender,
+    )]
+    pub loan_offer: Account<'info, LoanOffer>,
+}
+
+#[derive(Accounts)]
+pub struct TakeLoan<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    /// CHECK: Lender receives the closed loan offer rent — validated against loan_offer.lender
+    #[account(mut, constraint = lender.key() == loan_offer.lender @ MarketplaceError::InvalidLender)]
+    pub lender: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Blocklist PDA of nft_mint; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()], bump)]
+    pub mint_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the borrower; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), borrower.key().as_ref()], bump)]
+    pub borrower_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Blocklist PDA of the lender; usually uninitialised, checked in the handler
+    #[account(seeds = [b"blocklist".as_ref(), marketplace.instance_seed(), lender.key().as_ref()], bump)]
+    pub lender_blocklist: UncheckedAccount<'info>,
+
+    /// CHECK: Metaplex metadata for nft_mint — owner, address and collection are verified in the handler
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"loan_offer".as_ref(), marketplace.instance_seed(), loan_offer.collection.as_ref(), lender.key().as_ref()],
+        bump = loan_offer.bump,
+        close = lender,
+    )]
+    pub loan_offer: Account<'info, LoanOffer>,
+
+    #[account(
+        init,
+        payer = borrower,
+        space = 8 + Loan::INIT_SPACE,
+        seeds = [b"loan".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump,
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// Escrow authority PDA — shared with listings, init_if_needed so it persists
+    #[account(
+        init_if_needed,
+        payer = borrower,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = borrower,
+    )]
+    pub borrower_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = borrower,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RepayLoan<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    /// CHECK: Lender receives the repayment — validated against loan.lender
+    #[account(mut, constraint = lender.key() == loan.lender @ MarketplaceError::InvalidLender)]
+    pub lender: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"loan".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = loan.bump,
+        constraint = borrower.key() == loan.borrower @ MarketplaceError::InvalidBorrower,
+        close = borrower,
+    )]
+    pub loan: Account<'info, Loan>,
+
+    #[account(
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = escrow.bump,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = borrower,
+        associated_token::mint = nft_mint,
+        associated_token::authority = borrower,
+    )]
+    pub borrower_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ForecloseLoan<'info> {
+    #[account(mut)]
+    pub lender: Signer<'info>,
+
+    /// CHECK: Borrower receives the closed loan rent — validated against loan.borrower
+    #[account(mut, constraint = borrower.key() == loan.borrower @ MarketplaceError::InvalidBorrower)]
+    pub borrower: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"loan".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = loan.bump,
+        constraint = lender.key() == loan.lender @ MarketplaceError::InvalidLender,
+        close = borrower,
+    )]
+    pub loan: Account<'info, Loan>,
+
+    #[account(
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = escrow.bump,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = lender,
+        associated_token::mint = nft_mint,
+        associated_token::authority = lender,
+    )]
+    pub lender_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BeginWindDown<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + WindDownReport::INIT_SPACE,
+        seeds = [b"wind_down".as_ref(), marketplace.instance_seed()],
+        bump,
+    )]
+    pub wind_down_report: Account<'info, WindDownReport>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReturnListing<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"wind_down".as_ref(), marketplace.instance_seed()],
+        bump = wind_down_report.bump,
+    )]
+    pub wind_down_report: Account<'info, WindDownReport>,
+
+    /// CHECK: Seller receives the NFT and the listing rent — validated against listing.seller
+    #[account(mut, constraint = seller.key() == listing.seller @ MarketplaceError::InvalidSeller)]
+    pub seller: UncheckedAccount<'info>,
+
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = listing.bump,
+        close = seller,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow".as_ref(), marketplace.instance_seed(), nft_mint.key().as_ref()],
+        bump = escrow.bump,
+        close = seller,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    /// Created for in-place listings to clear the escrow's delegation, then closed
     #[account(
         init_if_needed,
-        payer = seller,
+        payer = payer,
         associated_token::mint = nft_mint,
         associated_token::authority = escrow,
     )]
     pub escrow_token_account: Account<'info, TokenAccount>,
 
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = nft_mint,
+        associated_token::authority = seller,
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Metaplex edition PDA — required for non-custodial listings, validated by the metadata program
+    pub nft_edition: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metaplex token metadata program — required for non-custodial listings
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ReturnListing<'info> {
+    fn custody(&self) -> ListingCustody<'_, 'info> {
+        ListingCustody {
+            nft_mint: self.nft_mint.as_ref(),
+            escrow: self.escrow.as_ref(),
+            escrow_token_account: Some(self.escrow_token_account.as_ref()),
+            seller_token_account: Some(self.seller_token_account.as_ref()),
+            nft_edition: self.nft_edition.as_ref().map(|a| a.as_ref()),
+            token_metadata_program: self.token_metadata_program.as_ref().map(|a| a.as_ref()),
+            token_program: self.token_program.as_ref(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct RefundOffer<'info> {
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"wind_down".as_ref(), marketplace.instance_seed()],
+        bump = wind_down_report.bump,
+    )]
+    pub wind_down_report: Account<'info, WindDownReport>,
+
+    /// CHECK: Offerer receives the escrowed SOL and rent — validated by the offer seeds
+    #[account(mut)]
+    pub offerer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"offer".as_ref(), marketplace.instance_seed(), offer.nft_mint.as_ref(), offerer.key().as_ref()],
+        bump = offer.bump,
+        close = offerer,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// Absent for pool-backed offers
+    #[account(
+        mut,
+        seeds = [b"offer_escrow".as_ref(), marketplace.instance_seed(), offer.nft_mint.as_ref(), offerer.key().as_ref()],
+        bump = offer_escrow.bump,
+        close = offerer,
+    )]
+    pub offer_escrow: Option<Account<'info, OfferEscrow>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundBiddingPool<'info> {
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"wind_down".as_ref(), marketplace.instance_seed()],
+        bump = wind_down_report.bump,
+    )]
+    pub wind_down_report: Account<'info, WindDownReport>,
+
+    /// CHECK: Pool owner receives the balance and rent — validated by the pool seeds
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"bidding_pool".as_ref(), marketplace.instance_seed(), owner.key().as_ref()],
+        bump = bidding_pool.bump,
+        close = owner,
+    )]
+    pub bidding_pool: Account<'info, BiddingPool>,
+}
+
+#[derive(Accounts)]
+pub struct ReturnLoanOffer<'info> {
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"wind_down".as_ref(), marketplace.instance_seed()],
+        bump = wind_down_report.bump,
+    )]
+    pub wind_down_report: Account<'info, WindDownReport>,
+
+    /// CHECK: Lender receives the principal and rent — validated by the loan offer seeds
+    #[account(mut)]
+    pub lender: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"loan_offer".as_ref(), marketplace.instance_seed(), loan_offer.collection.as_ref(), lender.key().as_ref()],
+        bump = loan_offer.bump,
+        close = lender,
+    )]
+    pub loan_offer: Account<'info, LoanOffer>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteWindDown<'info> {
+    #[account(
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"wind_down".as_ref(), marketplace.instance_seed()],
+        bump = wind_down_report.bump,
+    )]
+    pub wind_down_report: Account<'info, WindDownReport>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateMarketplace<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+        realloc = 8 + Marketplace::INIT_SPACE,
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateListing<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"listing".as_ref(), marketplace.instance_seed(), listing.nft_mint.as_ref()],
+        bump = listing.bump,
+        realloc = 8 + Listing::INIT_SPACE,
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateOffer<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"marketplace".as_ref(), marketplace.instance_seed()],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"offer".as_ref(), marketplace.instance_seed(), offer.nft_mint.as_ref(), offer.offerer.as_ref()],
+        bump = offer.bump,
+        realloc = 8 + Offer::INIT_SPACE,
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// ─── Account Data ────────────────────────────────────────────────────────────
+
+/// Implements the Anchor account traits for a versioned account. The version byte sits
+/// right after the fixed-size `legacy` prefix: data is read as the current layout when
+/// it is non-zero, and as the legacy layout when it is zero or the account ends there.
+/// Version 0 accounts are written back in the legacy layout so they keep fitting their
+/// allocation until migrated.
+macro_rules! versioned_account {
+    ($name:ident) => {
+        impl anchor_lang::Discriminator for $name {
+            const DISCRIMINATOR: [u8; 8] =
+                <legacy::$name as anchor_lang::Discriminator>::DISCRIMINATOR;
+        }
+
+        impl anchor_lang::Owner for $name {
+            fn owner() -> Pubkey {
+                crate::ID
+            }
+        }
+
+        impl anchor_lang::AccountSerialize for $name {
+            fn try_serialize<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+                writer
+                    .write_all(&<Self as anchor_lang::Discriminator>::DISCRIMINATOR)
+                    .map_err(|_| ErrorCode::AccountDidNotSerialize)?;
+                let written = if self.version == 0 {
+                    let legacy = self
+                        .to_legacy()
+                        .ok_or(MarketplaceError::AccountNotMigrated)?;
+                    AnchorSerialize::serialize(&legacy, writer)
+                } else {
+                    AnchorSerialize::serialize(self, writer)
+                };
+                written.map_err(|_| ErrorCode::AccountDidNotSerialize.into())
+            }
+        }
+
+        impl anchor_lang::AccountDeserialize for $name {
+            fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+                let discriminator = <Self as anchor_lang::Discriminator>::DISCRIMINATOR;
+                if buf.len() < discriminator.len() {
+                    return err!(ErrorCode::AccountDiscriminatorNotFound);
+                }
+                if buf[..8] != discriminator {
+                    return Err(error!(ErrorCode::AccountDiscriminatorMismatch)
+                        .with_account_name(stringify!($name)));
+                }
+                Self::try_deserialize_unchecked(buf)
+            }
+
+            fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+                let mut data: &[u8] = &buf[8..];
+                let parsed = match data.get(<legacy::$name as anchor_lang::Space>::INIT_SPACE) {
+                    None | Some(0) => <legacy::$name as AnchorDeserialize>::deserialize(&mut data)
+                        .map(Self::from_legacy),
+                    Some(_) => AnchorDeserialize::deserialize(&mut data),
+                };
+                parsed.map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+            }
+        }
+    };
+}
+
+/// Layouts of accounts created before they carried a version byte. Each shares its
+/// discriminator with the current type, whose layout starts with the same fields.
+///
+/// Migrate a marketplace with `migrate_marketplace` before its listings and offers:
+/// `migrate_listing` and `migrate_offer` count them in the marketplace's new fields, and
+/// opening anything new fails with `MarketplaceNotMigrated` until then. Exits keep
+/// working on a legacy marketplace, since its legacy listings and offers are uncounted.
+pub mod legacy {
+    use super::*;
+
+    #[account]
+    #[derive(InitSpace)]
+    pub struct Marketplace {
+        pub admin: Pubkey,
+        pub fee_recipient: Pubkey,
+        pub fee_bps: u16,
+        pub paused: bool,
+        pub listing_count: u64,
+        pub bump: u8,
+    }
+
+    #[account]
+    #[derive(InitSpace)]
+    pub struct Listing {
+        pub seller: Pubkey,
+        pub nft_mint: Pubkey,
+        pub price: u64,
+        pub expiration_time: i64,
+        pub is_active: bool,
+        pub is_auction: bool,
+        pub highest_bid: u64,
+        pub highest_bidder: Pubkey,
+        pub created_at: i64,
+        pub bump: u8,
+    }
+
+    #[account]
+    #[derive(InitSpace)]
+    pub struct Offer {
+        pub offerer: Pubkey,
+        pub nft_mint: Pubkey,
+        pub amount: u64,
+        pub expiration_time: i64,
+        pub is_active: bool,
+        pub created_at: i64,
+        pub bump: u8,
+    }
+}
+
+/// Mark a reallocated account as being in the `current` layout. Accounts already
+/// there are left alone, so clients can prepend a migration unconditionally.
+fn upgrade_version(version: &mut u8, current: u8, account: Pubkey) {
+    if *version < current {
+        *version = current;
+        emit!(AccountMigrated {
+            account,
+            version: current,
+        });
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, InitSpace)]
+pub struct Marketplace {
+    pub admin: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub fee_bps: u16,
+    /// Mask of `PAUSE_*` bits for the operations currently paused.
+    pub paused: u8,
+    pub listing_count: u64,
+    pub bump: u8,
+    /// Layout version; 0 for accounts still in the `legacy` layout.
+    pub version: u8,
+    /// Storefront ID; `Pubkey::default()` for the original singleton marketplace.
+    pub instance: Pubkey,
+    pub pauser: Pubkey,
+    pub fee_manager: Pubkey,
+    pub listing_moderator: Pubkey,
+    pub treasurer: Pubkey,
+    /// Nominee of an admin transfer awaiting `accept_admin`.
+    pub pending_admin: Option<Pubkey>,
+    /// Set by `begin_wind_down`; blocks all new activity for good.
+    pub winding_down: bool,
+    /// Listings whose NFT is still in custody.
+    pub active_listings: u64,
+    /// Offers not yet accepted, cancelled or refunded.
+    pub open_offers: u64,
+    /// Bidding pools not yet closed.
+    pub bidding_pools: u64,
+    /// Held sales whose proceeds are not yet released or resolved.
+    pub open_settlements: u64,
+    /// Loan offers whose principal is still escrowed.
+    pub open_loan_offers: u64,
+    /// Loans whose collateral is still escrowed.
+    pub active_loans: u64,
+    /// Zeroed space for fields added by later versions.
+    pub reserved: [u8; 15],
+}
+
+versioned_account!(Marketplace);
+
+impl Marketplace {
+    pub const VERSION: u8 = 1;
+
+    fn from_legacy(legacy: legacy::Marketplace) -> Self {
+        Self {
+            admin: legacy.admin,
+            fee_recipient: legacy.fee_recipient,
+            fee_bps: legacy.fee_bps,
+            // The old flag paused everything
+            paused: if legacy.paused { Self::PAUSE_ALL } else { 0 },
+            listing_count: legacy.listing_count,
+            bump: legacy.bump,
+            version: 0,
+            instance: Pubkey::default(),
+            pauser: legacy.admin,
+            fee_manager: legacy.admin,
+            listing_moderator: legacy.admin,
+            treasurer: legacy.admin,
+            pending_admin: None,
+            winding_down: false,
+            active_listings: 0,
+            open_offers: 0,
+            bidding_pools: 0,
+            open_settlements: 0,
+            open_loan_offers: 0,
+            active_loans: 0,
+            reserved: [0; 15],
+        }
+    }
+
+    /// Legacy layout of this account, or `None` if a field it cannot hold changed.
+    fn to_legacy(&self) -> Option<legacy::Marketplace> {
+        let legacy = legacy::Marketplace {
+            admin: self.admin,
+            fee_recipient: self.fee_recipient,
+            fee_bps: self.fee_bps,
+            paused: self.paused != 0,
+            listing_count: self.listing_count,
+            bump: self.bump,
+        };
+        (Self::from_legacy(legacy.clone()) == *self).then_some(legacy)
+    }
+
+    /// Seed scoping this instance's PDAs. Empty for the default instance, so its
+    /// accounts keep the addresses they had before instances existed.
+    pub fn instance_seed(&self) -> &[u8] {
+        if self.instance == Pubkey::default() {
+            &[]
+        } else {
+            self.instance.as_ref()
+        }
+    }
+
+    fn init(
+        &mut self,
+        instance: Pubkey,
+        admin: Pubkey,
+        fee_recipient: Pubkey,
+        fee_bps: u16,
+        bump: u8,
+    ) -> Result<()> {
+        require!(fee_bps <= 1000, MarketplaceError::FeeTooHigh);
+
+        self.instance = instance;
+        self.admin = admin;
+        self.fee_recipient = fee_recipient;
+        self.fee_bps = fee_bps;
+        self.paused = 0;
+        self.listing_count = 0;
+        // The admin holds every role until it hands them to dedicated keys
+        self.pauser = admin;
+        self.fee_manager = admin;
+        self.listing_moderator = admin;
+        self.treasurer = admin;
+        self.pending_admin = None;
+        self.winding_down = false;
+        self.active_listings = 0;
+        self.open_offers = 0;
+        self.bidding_pools = 0;
+        self.open_settlements = 0;
+        self.open_loan_offers = 0;
+        self.active_loans = 0;
+        self.bump = bump;
+        self.version = Self::VERSION;
+
+        emit!(MarketplaceInitialized {
+            instance,
+            admin,
+            fee_recipient,
+            fee_bps,
+        });
+
+        Ok(())
+    }
+
+    /// New listings, repricing and batch listing.
+    pub const PAUSE_LISTING: u8 = 1 << 0;
+    /// `buy_nft` and `buy_many`.
+    pub const PAUSE_BUYING: u8 = 1 << 1;
+    /// Making and accepting offers.
+    pub const PAUSE_OFFERS: u8 = 1 << 2;
+    /// New auction listings.
+    pub const PAUSE_AUCTIONS: u8 = 1 << 3;
+    /// Fee distribution and emergency withdrawals from the treasury.
+    pub const PAUSE_WITHDRAWALS: u8 = 1 << 4;
+    /// New loan offers and loans.
+    pub const PAUSE_LENDING: u8 = 1 << 5;
+    pub const PAUSE_ALL: u8 = Self::PAUSE_LISTING
+        | Self::PAUSE_BUYING
+        | Self::PAUSE_OFFERS
+        | Self::PAUSE_AUCTIONS
+        | Self::PAUSE_WITHDRAWALS
+        | Self::PAUSE_LENDING;
+
+    pub fn validate_pause_flags(flags: u8) -> Result<()> {
+        require!(
+            flags != 0 && flags & !Self::PAUSE_ALL == 0,
+            MarketplaceError::InvalidPauseFlags
+        );
+        Ok(())
+    }
+
+    /// Operations refused for good once the marketplace is winding down.
+    pub const WIND_DOWN_BLOCKED: u8 = Self::PAUSE_ALL & !Self::PAUSE_WITHDRAWALS;
+
+    /// Fail if any operation in `flags` is paused, or blocked by a wind-down.
+    pub fn require_unpaused(&self, flags: u8) -> Result<()> {
+        require!(
+            self.paused & flags == 0,
+            MarketplaceError::MarketplacePaused
+        );
+        require!(
+            !self.winding_down || flags & Self::WIND_DOWN_BLOCKED == 0,
+            MarketplaceError::MarketplaceWindingDown
+        );
+        Ok(())
+    }
+
+    /// Fail while this account is still in the legacy layout, which has no room for
+    /// the counters and roles the current one tracks.
+    pub fn require_migrated(&self) -> Result<()> {
+        require!(self.version > 0, MarketplaceError::MarketplaceNotMigrated);
+        Ok(())
+    }
+
+    /// Revision a listing opened now starts at. `listing_count` grows with every listing,
+    /// and no listing is edited 2^32 times, so it exceeds any revision an earlier,
+    /// since closed, listing of the same mint reached.
+    fn first_listing_revision(&self) -> Result<u64> {
+        self.listing_count
+            .checked_add(1)
+            .and_then(|next_listing| next_listing.checked_mul(1 << 32))
+            .ok_or(MarketplaceError::Overflow.into())
+    }
+
+    fn listings_opened(&mut self, count: u64) -> Result<()> {
+        self.require_migrated()?;
+        self.listing_count = self
+            .listing_count
+            .checked_add(count)
+            .ok_or(MarketplaceError::Overflow)?;
+        self.active_listings = self
+            .active_listings
+            .checked_add(count)
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(())
+    }
+
+    /// Listings opened before `active_listings` existed are left out until migrated.
+    fn listing_closed(&mut self, listing: &Listing) {
+        if listing.counted {
+            self.active_listings = self.active_listings.saturating_sub(1);
+        }
+    }
+
+    fn offer_opened(&mut self) -> Result<()> {
+        self.require_migrated()?;
+        self.open_offers = self
+            .open_offers
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(())
+    }
+
+    fn offer_closed(&mut self, offer: &Offer) {
+        if offer.counted {
+            self.open_offers = self.open_offers.saturating_sub(1);
+        }
+    }
+
+    fn bidding_pool_opened(&mut self) -> Result<()> {
+        self.require_migrated()?;
+        self.bidding_pools = self
+            .bidding_pools
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(())
+    }
+
+    fn bidding_pools_closed(&mut self, count: u64) {
+        self.bidding_pools = self.bidding_pools.saturating_sub(count);
+    }
+
+    fn settlement_opened(&mut self) -> Result<()> {
+        self.require_migrated()?;
+        self.open_settlements = self
+            .open_settlements
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(())
+    }
+
+    fn settlement_closed(&mut self) {
+        self.open_settlements = self.open_settlements.saturating_sub(1);
+    }
+
+    fn loan_offer_opened(&mut self) -> Result<()> {
+        self.require_migrated()?;
+        self.open_loan_offers = self
+            .open_loan_offers
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(())
+    }
+
+    fn loan_offer_closed(&mut self) {
+        self.open_loan_offers = self.open_loan_offers.saturating_sub(1);
+    }
+
+    fn loan_opened(&mut self) -> Result<()> {
+        self.require_migrated()?;
+        self.active_loans = self
+            .active_loans
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(())
+    }
+
+    fn loan_closed(&mut self) {
+        self.active_loans = self.active_loans.saturating_sub(1);
+    }
+
+    pub fn role_holder(&self, role: Role) -> Pubkey {
+        match role {
+            Role::Pauser => self.pauser,
+            Role::FeeManager => self.fee_manager,
+            Role::ListingModerator => self.listing_moderator,
+            Role::Treasurer => self.treasurer,
+        }
+    }
+
+    fn role_holder_mut(&mut self, role: Role) -> &mut Pubkey {
+        match role {
+            Role::Pauser => &mut self.pauser,
+            Role::FeeManager => &mut self.fee_manager,
+            Role::ListingModerator => &mut self.listing_moderator,
+            Role::Treasurer => &mut self.treasurer,
+        }
+    }
+
+    /// Whether `key` currently holds `role`; a revoked role is held by no one.
+    pub fn holds_role(&self, role: Role, key: &Pubkey) -> bool {
+        let holder = self.role_holder(role);
+        holder != Pubkey::default() && holder == *key
+    }
+
+    pub fn require_role(&self, role: Role, signer: &Pubkey) -> Result<()> {
+        require!(
+            self.holds_role(role, signer),
+            MarketplaceError::Unauthorized
+        );
+        Ok(())
+    }
+}
+
+/// Delegated admin powers, each held by its own key. `Marketplace.admin` only grants
+/// and revokes them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Pauses and unpauses trading.
+    Pauser,
+    /// Sets fees, fee tiers, referrers and collection configs.
+    FeeManager,
+    /// Cancels other sellers' listings.
+    ListingModerator,
+    /// Redirects and withdraws collected fees.
+    Treasurer,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, InitSpace)]
+pub struct Listing {
+    pub seller: Pubkey,
+    pub nft_mint: Pubkey,
+    pub price: u64,
+    pub expiration_time: i64,
+    pub is_active: bool,
+    pub is_auction: bool,
+    pub highest_bid: u64,
+    pub highest_bidder: Pubkey,
+    pub created_at: i64,
+    pub bump: u8,
+    /// Layout version; 0 for accounts still in the `legacy` layout.
+    pub version: u8,
+    /// True when the NFT sits in the escrow token account; false when it stays
+    /// frozen in the seller's wallet with the escrow PDA as delegate.
+    pub is_custodial: bool,
+    /// Set from `Marketplace::first_listing_revision` when the listing opens and
+    /// incremented whenever its terms change; buyers and sellers pass the revision they
+    /// saw so stale transactions fail, even against a later listing of the same mint.
+    pub revision: u64,
+    /// Offers at or above this amount settle inside `make_offer`.
+    pub auto_accept_price: Option<u64>,
+    /// Buying and offers are refused before this time; the NFT is held from listing.
+    pub start_time: Option<i64>,
+    /// Seconds a sale holds the seller's proceeds open to dispute; 0 pays at once.
+    pub hold_period: i64,
+    /// Gallery or curator managing the listing on the seller's behalf.
+    pub agent: Option<ConsignmentAgent>,
+    /// The NFT's verified collection when listed, whose config governs every sale.
+    /// `None` for listings opened before it was recorded.
+    pub collection: Option<Pubkey>,
+    /// Included in the marketplace's `active_listings`. False for legacy listings
+    /// until `migrate_listing` counts them.
+    pub counted: bool,
+    /// The only wallet that may buy, bid or make offers; `Pubkey::default()` when the
+    /// listing is open to anyone. Takes the space reserved for later fields.
+    pub reserved_buyer: Pubkey,
+}
+
+versioned_account!(Listing);
+
+impl Listing {
+    pub const VERSION: u8 = 1;
+
+    fn from_legacy(legacy: legacy::Listing) -> Self {
+        Self {
+            seller: legacy.seller,
+            nft_mint: legacy.nft_mint,
+            price: legacy.price,
+            expiration_time: legacy.expiration_time,
+            is_active: legacy.is_active,
+            is_auction: legacy.is_auction,
+            highest_bid: legacy.highest_bid,
+            highest_bidder: legacy.highest_bidder,
+            created_at: legacy.created_at,
+            bump: legacy.bump,
+            version: 0,
+            // Every listing predating in-place listings is held in escrow
+            is_custodial: true,
+            revision: 0,
+            auto_accept_price: None,
+            start_time: None,
+            hold_period: 0,
+            agent: None,
+            collection: None,
+            counted: false,
+            reserved_buyer: Pubkey::default(),
+        }
+    }
+
+    /// Legacy layout of this account, or `None` if a field it cannot hold changed.
+    fn to_legacy(&self) -> Option<legacy::Listing> {
+        let legacy = legacy::Listing {
+            seller: self.seller,
+            nft_mint: self.nft_mint,
+            price: self.price,
+            expiration_time: self.expiration_time,
+            is_active: self.is_active,
+            is_auction: self.is_auction,
+            highest_bid: self.highest_bid,
+            highest_bidder: self.highest_bidder,
+            created_at: self.created_at,
+            bump: self.bump,
+        };
+        (Self::from_legacy(legacy.clone()) == *self).then_some(legacy)
+    }
+
+    /// (Re)initialise this listing as active with the given terms. The caller sets
+    /// `revision` from `Marketplace::first_listing_revision`.
+    pub fn open(
+        &mut self,
+        seller: Pubkey,
+        nft_mint: Pubkey,
+        terms: &ListingTerms,
+        is_custodial: bool,
+        bump: u8,
+        now: i64,
+    ) -> Result<()> {
+        self.seller = seller;
+        self.nft_mint = nft_mint;
+        if let Some(start_time) = terms.start_time {
+            require!(start_time > now, MarketplaceError::InvalidStartTime);
+        }
+        self.price = terms.price;
+        // The listing runs for the full duration once it opens
+        self.expiration_time = terms
+            .start_time
+            .unwrap_or(now)
+            .checked_add(terms.duration)
+            .ok_or(MarketplaceError::Overflow)?;
+        self.start_time = terms.start_time;
+        self.hold_period = terms.hold_period;
+        self.agent = None;
+        self.reserved_buyer = Pubkey::default();
+        self.is_active = true;
+        self.counted = true;
+        self.is_auction = terms.is_auction;
+        self.is_custodial = is_custodial;
+        self.auto_accept_price = terms.auto_accept_price;
+        self.highest_bid = 0;
+        self.highest_bidder = Pubkey::default();
+        self.created_at = now;
+        self.bump = bump;
+        self.version = Self::VERSION;
+        Ok(())
+    }
+
+    pub fn set_price(&mut self, new_price: u64) -> Result<()> {
+        ListingTerms::validate_auto_accept(new_price, self.is_auction, self.auto_accept_price)?;
+        self.price = new_price;
+        self.bump_revision()
+    }
+
+    pub fn set_auto_accept_price(&mut self, auto_accept_price: Option<u64>) -> Result<()> {
+        ListingTerms::validate_auto_accept(self.price, self.is_auction, auto_accept_price)?;
+        self.auto_accept_price = auto_accept_price;
+        self.bump_revision()
+    }
+
+    /// Whether an offer of `amount` should fill this listing at once.
+    pub fn auto_accepts(&self, amount: u64, now: i64) -> bool {
+        self.auto_accept_price
+            .is_some_and(|threshold| amount >= threshold)
+            && now < self.expiration_time
+    }
+
+    /// Apply a seller's edit. The expiration may only be extended, and the listing may
+    /// switch between fixed price and auction or change its reserved buyer only while
+    /// there are no bids.
+    pub fn apply(&mut self, update: &ListingUpdate, now: i64) -> Result<()> {
+        if let Some(price) = update.price {
+            require!(price > 0, MarketplaceError::PriceMustBePositive);
+            self.price = price;
+        }
+        if let Some(expiration_time) = update.expiration_time {
+            require!(
+                expiration_time > self.expiration_time && expiration_time > now,
+                MarketplaceError::InvalidExpiration
+            );
+            self.expiration_time = expiration_time;
+        }
+        if let Some(is_auction) = update.is_auction {
+            if is_auction != self.is_auction {
+                require!(self.highest_bid == 0, MarketplaceError::AuctionHasBids);
+                self.is_auction = is_auction;
+            }
+        }
+        if let Some(reserved_buyer) = update.reserved_buyer {
+            if reserved_buyer != self.reserved_buyer {
+                require!(self.highest_bid == 0, MarketplaceError::AuctionHasBids);
+                require!(
+                    reserved_buyer != self.seller,
+                    MarketplaceError::InvalidReservedBuyer
+                );
+                self.reserved_buyer = reserved_buyer;
+            }
+        }
+        ListingTerms::validate_auto_accept(self.price, self.is_auction, self.auto_accept_price)?;
+        self.bump_revision()
+    }
+
+    pub fn set_agent(&mut self, agent: Option<ConsignmentAgent>) -> Result<()> {
+        if let Some(agent) = &agent {
+            agent.validate(&self.seller)?;
+        }
+        self.agent = agent;
+        self.bump_revision()
+    }
+
+    /// Require `signer` to be the seller or the listing's consignment agent.
+    /// `agent_did` must be the agent's profile when the agent is a DID.
+    pub fn require_manager(
+        &self,
+        signer: &Pubkey,
+        agent_did: Option<&Account<DidProfile>>,
+    ) -> Result<()> {
+        if *signer == self.seller {
+            return Ok(());
+        }
+        let agent = self.agent.as_ref().ok_or(MarketplaceError::InvalidSeller)?;
+        require_keys_eq!(
+            *signer,
+            agent.wallet(agent_did)?,
+            MarketplaceError::InvalidSeller
+        );
+        Ok(())
+    }
+
+    /// Add the agent's commission, if any, to the payout of a sale at `price`.
+    pub fn charge_commission(
+        &self,
+        payout: &mut SalePayout,
+        price: u64,
+        agent_did: Option<&Account<DidProfile>>,
+    ) -> Result<()> {
+        match &self.agent {
+            Some(agent) => {
+                payout.add_commission(agent.wallet(agent_did)?, price, agent.commission_bps)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `buyer` may buy, bid or make offers on this listing.
+    pub fn is_open_to(&self, buyer: &Pubkey) -> bool {
+        self.reserved_buyer == Pubkey::default() || self.reserved_buyer == *buyer
+    }
+
+    pub fn require_open_to(&self, buyer: &Pubkey) -> Result<()> {
+        require!(
+            self.is_open_to(buyer),
+            MarketplaceError::ReservedForAnotherBuyer
+        );
+        Ok(())
+    }
+
+    pub fn has_started(&self, now: i64) -> bool {
+        now >= self.start_time.unwrap_or(now)
+    }
+
+    pub fn require_started(&self, now: i64) -> Result<()> {
+        require!(self.has_started(now), MarketplaceError::ListingNotStarted);
+        Ok(())
+    }
+
+    fn bump_revision(&mut self) -> Result<()> {
+        self.revision = self
+            .revision
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(())
+    }
+}
+
+/// Seller-chosen terms for a new listing.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ListingTerms {
+    pub price: u64,
+    pub duration: i64,
+    pub is_auction: bool,
+    /// Offers at or above this amount are accepted automatically.
+    pub auto_accept_price: Option<u64>,
+    /// Future time the listing opens; `None` opens it immediately.
+    pub start_time: Option<i64>,
+    /// Buyer-protection window in seconds; 0 pays the seller at once.
+    pub hold_period: i64,
+}
+
+/// A gallery or curator a seller has authorised to reprice their listing and accept
+/// offers on it, in return for a commission on the sale.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, InitSpace)]
+pub struct ConsignmentAgent {
+    /// The agent's wallet, or its `anft_did` profile when `is_did` is set.
+    pub key: Pubkey,
+    /// Resolve `key` to the DID's current wallet, so the agent survives key rotation.
+    pub is_did: bool,
+    /// Share of the sale price paid to the agent out of the seller's proceeds.
+    pub commission_bps: u16,
+}
+
+impl ConsignmentAgent {
+    pub const MAX_COMMISSION_BPS: u16 = 5_000;
+
+    pub fn validate(&self, seller: &Pubkey) -> Result<()> {
+        require!(
+            self.key != Pubkey::default() && self.key != *seller,
+            MarketplaceError::InvalidAgent
+        );
+        require!(
+            self.commission_bps <= Self::MAX_COMMISSION_BPS,
+            MarketplaceError::InvalidCommission
+        );
+        Ok(())
+    }
+
+    /// Wallet acting for the agent: `key` itself, or the current wallet of its DID.
+    pub fn wallet(&self, agent_did: Option<&Account<DidProfile>>) -> Result<Pubkey> {
+        if !self.is_did {
+            return Ok(self.key);
+        }
+        let agent_did = agent_did.ok_or(MarketplaceError::InvalidAgent)?;
+        require_keys_eq!(agent_did.key(), self.key, MarketplaceError::InvalidAgent);
+        Ok(agent_did.current_wallet)
+    }
+}
+
+/// Seller edits to a live listing; `None` leaves a field unchanged. Listings settle
+/// only in native SOL, so there is no currency to change.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ListingUpdate {
+    pub price: Option<u64>,
+    pub expiration_time: Option<i64>,
+    pub is_auction: Option<bool>,
+    /// Reserve the listing for one wallet, or `Pubkey::default()` to open it to anyone.
+    pub reserved_buyer: Option<Pubkey>,
+}
+
+impl ListingUpdate {
+    /// Pause bits that block this edit.
+    pub fn pause_flags(&self) -> u8 {
+        if self.is_auction == Some(true) {
+            Marketplace::PAUSE_LISTING | Marketplace::PAUSE_AUCTIONS
+        } else {
+            Marketplace::PAUSE_LISTING
+        }
+    }
+}
+
+impl ListingTerms {
+    /// Pause bits that block listing with these terms.
+    pub fn pause_flags(&self) -> u8 {
+        if self.is_auction {
+            Marketplace::PAUSE_LISTING | Marketplace::PAUSE_AUCTIONS
+        } else {
+            Marketplace::PAUSE_LISTING
+        }
+    }
+
+    /// Platform-wide minimum listing duration, unless a collection config overrides it.
+    pub const MIN_DURATION: i64 = 86_400;
+
+    /// Longest buyer-protection window a seller may choose.
+    pub const MAX_HOLD_PERIOD: i64 = 30 * 86_400;
+
+    /// Check the terms against the platform rules and the NFT's collection config, if any.
+    /// Listings settle in native SOL, so the collection must allow it.
+    pub fn validate(&self, collection: Option<&CollectionConfig>) -> Result<()> {
+        require!(self.price > 0, MarketplaceError::PriceMustBePositive);
+        let min_duration = collection
+            .and_then(|config| config.min_listing_duration)
+            .unwrap_or(Self::MIN_DURATION);
+        require!(
+            self.duration >= min_duration,
+            MarketplaceError::DurationTooShort
+        );
+        require!(
+            (0..=Self::MAX_HOLD_PERIOD).contains(&self.hold_period),
+            MarketplaceError::InvalidHoldPeriod
+        );
+        if let Some(config) = collection {
+            require!(
+                config.allows_currency(&Pubkey::default()),
+                MarketplaceError::CurrencyNotAllowed
+            );
+        }
+        Self::validate_auto_accept(self.price, self.is_auction, self.auto_accept_price)
+    }
+
+    /// An auto-accept threshold must be positive and no higher than the price, where a
+    /// buyer would simply buy. Auctions settle through bidding instead.
+    pub fn validate_auto_accept(
+        price: u64,
+        is_auction: bool,
+        auto_accept_price: Option<u64>,
+    ) -> Result<()> {
+        if let Some(threshold) = auto_accept_price {
+            require!(
+                !is_auction && threshold > 0 && threshold <= price,
+                MarketplaceError::InvalidAutoAcceptPrice
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct FeeTier {
+    /// Lamports of marketplace fee the seller must have paid to reach the tier.
+    pub min_fees_paid: u64,
+    pub fee_bps: u16,
+}
+
+/// Admin-set fee tiers by the marketplace fees a seller has paid, sorted ascending.
+/// Tiers are keyed by `SellerStats`, which only the program writes, rather than by
+/// DID attestations, whose counts the profile owner controls.
+#[account]
+#[derive(InitSpace)]
+pub struct FeeTierTable {
+    #[max_len(8)]
+    pub tiers: Vec<FeeTier>,
+    pub bump: u8,
+}
+
+impl FeeTierTable {
+    pub const MAX_TIERS: usize = 8;
+
+    /// Deserialize `info` if the admin has set fee tiers. The caller is responsible
+    /// for checking the address.
+    pub fn load(info: &AccountInfo) -> Result<Option<Self>> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self::try_deserialize(
+            &mut &info.try_borrow_data()?[..],
+        )?))
+    }
+
+    /// Fee of the highest tier `fees_paid` reaches, if any.
+    pub fn fee_bps_for(&self, fees_paid: u64) -> Option<u16> {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| fees_paid >= tier.min_fees_paid)
+            .map(|tier| tier.fee_bps)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct FeeShare {
+    pub recipient: Pubkey,
+    pub bps: u16,
+}
+
+impl FeeShare {
+    pub fn portion_of(&self, total: u64) -> Result<u64> {
+        let portion = (total as u128)
+            .checked_mul(self.bps as u128)
+            .ok_or(MarketplaceError::Overflow)?
+            / 10_000;
+        u64::try_from(portion).map_err(|_| MarketplaceError::Overflow.into())
+    }
+}
+
+/// Collects marketplace fees and splits them between recipients by basis points.
+/// Rounding dust stays in the treasury for the next distribution.
+#[account]
+#[derive(InitSpace)]
+pub struct Treasury {
+    #[max_len(5)]
+    pub recipients: Vec<FeeShare>,
+    pub bump: u8,
+}
+
+impl Treasury {
+    pub const MAX_RECIPIENTS: usize = 5;
+
+    pub fn validate_recipients(recipients: &[FeeShare]) -> Result<()> {
+        require!(
+            !recipients.is_empty() && recipients.len() <= Self::MAX_RECIPIENTS,
+            MarketplaceError::InvalidTreasuryRecipients
+        );
+        require!(
+            recipients
+                .iter()
+                .all(|share| share.recipient != Pubkey::default()),
+            MarketplaceError::InvalidTreasuryRecipients
+        );
+        let total_bps: u32 = recipients.iter().map(|share| share.bps as u32).sum();
+        require!(
+            total_bps == 10_000,
+            MarketplaceError::InvalidTreasuryRecipients
+        );
+        Ok(())
+    }
+
+    /// Lamports held above the treasury's rent-exempt minimum.
+    pub fn distributable_lamports(treasury: &AccountInfo) -> Result<u64> {
+        let min_balance = Rent::get()?.minimum_balance(treasury.data_len());
+        Ok(treasury.lamports().saturating_sub(min_balance))
+    }
+}
+
+/// A seller's sales on one instance, recorded by the program to select their fee tier.
+#[account]
+#[derive(InitSpace)]
+pub struct SellerStats {
+    pub seller: Pubkey,
+    pub sales: u64,
+    /// Lamports of marketplace fee paid on the seller's sales, net of referral shares.
+    pub fees_paid: u64,
+    pub bump: u8,
+}
+
+impl SellerStats {
+    /// Deserialize `info` if the seller's stats have been opened. The caller is
+    /// responsible for checking the address.
+    pub fn load(info: &AccountInfo) -> Result<Option<Self>> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self::try_deserialize(
+            &mut &info.try_borrow_data()?[..],
+        )?))
+    }
+
+    /// `load` for an account whose address has not been checked yet.
+    pub fn load_at(
+        info: &AccountInfo,
+        instance_seed: &[u8],
+        seller: &Pubkey,
+    ) -> Result<Option<Self>> {
+        let (expected, _) = Pubkey::find_program_address(
+            &[b"seller_stats", instance_seed, seller.as_ref()],
+            &crate::ID,
+        );
+        require_keys_eq!(info.key(), expected, MarketplaceError::InvalidBatchAccounts);
+        Self::load(info)
+    }
+
+    /// Record a sale that paid `fee` in `info`, if the seller's stats have been opened.
+    pub fn record_sale(info: &AccountInfo, fee: u64) -> Result<()> {
+        let Some(mut stats) = Self::load(info)? else {
+            return Ok(());
+        };
+        stats.sales = stats
+            .sales
+            .checked_add(1)
+            .ok_or(MarketplaceError::Overflow)?;
+        stats.fees_paid = stats
+            .fees_paid
+            .checked_add(fee)
+            .ok_or(MarketplaceError::Overflow)?;
+        stats.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])
+    }
+}
+
+/// A partner gallery or aggregator that earns part of the marketplace fee on
+/// sales it brings in.
+#[account]
+#[derive(InitSpace)]
+pub struct Referrer {
+    pub wallet: Pubkey,
+    /// Share of the marketplace fee paid to the referrer, in basis points.
+    pub share_bps: u16,
+    pub total_earned: u64,
+    pub bump: u8,
+}
+
+impl Referrer {
+    pub const MAX_SHARE_BPS: u16 = 5_000;
+}
+
+/// Admin overrides for NFTs in one verified collection.
+#[account]
+#[derive(InitSpace)]
+pub struct CollectionConfig {
+    pub collection: Pubkey,
+    /// Replaces `Marketplace.fee_bps` as the base rate; seller fee tiers still take precedence.
+    pub fee_bps: Option<u16>,
+    /// Pay creators their metadata royalty out of the seller's proceeds on every sale.
+    pub enforce_royalties: bool,
+    /// Replaces `ListingTerms::MIN_DURATION`.
+    pub min_listing_duration: Option<i64>,
+    /// Currencies listings may settle in, with `Pubkey::default()` for native SOL.
+    /// Empty allows any currency.
+    #[max_len(4)]
+    pub allowed_currencies: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl CollectionConfig {
+    pub const MAX_CURRENCIES: usize = 4;
+
+    pub fn allows_currency(&self, currency: &Pubkey) -> bool {
+        self.allowed_currencies.is_empty() || self.allowed_currencies.contains(currency)
+    }
+
+    /// Deserialize `info` if it holds a config; `None` for any other account. The caller
+    /// is responsible for checking the address.
+    pub fn load(info: &AccountInfo) -> Result<Option<Self>> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self::try_deserialize(
+            &mut &info.try_borrow_data()?[..],
+        )?))
+    }
+
+    /// The config of `collection`, read from `info`, which must be its PDA on the
+    /// instance; `None` if the admin has not configured the collection.
+    pub fn load_at(
+        info: &AccountInfo,
+        instance_seed: &[u8],
+        collection: &Pubkey,
+    ) -> Result<Option<Self>> {
+        let (expected, _) = Pubkey::find_program_address(
+            &[b"collection_config", instance_seed, collection.as_ref()],
+            &crate::ID,
+        );
+        require_keys_eq!(info.key(), expected, MarketplaceError::InvalidCollection);
+        Self::load(info)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct CollectionConfigArgs {
+    pub fee_bps: Option<u16>,
+    pub enforce_royalties: bool,
+    pub min_listing_duration: Option<i64>,
+    #[max_len(4)]
+    pub allowed_currencies: Vec<Pubkey>,
+}
+
+impl CollectionConfigArgs {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(fee_bps) = self.fee_bps {
+            require!(fee_bps <= 1000, MarketplaceError::FeeTooHigh);
+        }
+        if let Some(min_listing_duration) = self.min_listing_duration {
+            require!(min_listing_duration > 0, MarketplaceError::DurationTooShort);
+        }
+        require!(
+            self.allowed_currencies.len() <= CollectionConfig::MAX_CURRENCIES,
+            MarketplaceError::TooManyCurrencies
+        );
+        Ok(())
+    }
+}
+
+/// A moderator flag on a mint or wallet. Sale paths pass the PDA for every key they
+/// touch and refuse to proceed while it exists.
+#[account]
+#[derive(InitSpace)]
+pub struct BlocklistEntry {
+    pub key: Pubkey,
+    /// Moderator-defined reason code, e.g. stolen, sanctioned or disputed.
+    pub reason: u16,
+    pub flagged_by: Pubkey,
+    pub flagged_at: i64,
+    pub bump: u8,
+}
+
+impl BlocklistEntry {
+    /// Fail if `entry`, the blocklist PDA of `key`, has been created. The caller is
+    /// responsible for checking the address.
+    pub fn require_clear(entry: &AccountInfo, key: &Pubkey) -> Result<()> {
+        if entry.owner != &crate::ID || entry.data_is_empty() {
+            return Ok(());
+        }
+        let data = entry.try_borrow_data()?;
+        let flagged = Self::try_deserialize(&mut &data[..])?;
+        emit!(BlockedActivity {
+            key: *key,
+            reason: flagged.reason,
+        });
+        err!(MarketplaceError::Blocklisted)
+    }
+
+    /// `require_clear` for an account whose address has not been checked yet.
+    pub fn require_clear_at(entry: &AccountInfo, instance_seed: &[u8], key: &Pubkey) -> Result<()> {
+        let (expected, _) =
+            Pubkey::find_program_address(&[b"blocklist", instance_seed, key.as_ref()], &crate::ID);
+        require_keys_eq!(
+            entry.key(),
+            expected,
+            MarketplaceError::InvalidBatchAccounts
+        );
+        Self::require_clear(entry, key)
+    }
+}
+
+/// An admin parameter change that only applies after a timelock.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub enum AdminChange {
+    Fee {
+        new_fee_bps: u16,
+    },
+    FeeRecipient {
+        new_fee_recipient: Pubkey,
+    },
+    EmergencyWithdraw {
+        amount: u64,
+    },
+    /// Replace the seller fee tiers, keyed by the marketplace fees a seller has paid as
+    /// the program records them in `SellerStats`. Recorded fees were paid, so wash sales
+    /// cost at least what they earn toward a tier.
+    FeeTiers {
+        #[max_len(8)]
+        tiers: Vec<FeeTier>,
+    },
+    /// Create or replace the overrides for NFTs in the verified collection `collection`.
+    CollectionConfig {
+        collection: Pubkey,
+        config: CollectionConfigArgs,
+    },
+    RemoveCollectionConfig {
+        collection: Pubkey,
+    },
+    /// Replace the treasury's recipients, creating the treasury and pointing the fee
+    /// recipient at it the first time.
+    TreasuryRecipients {
+        #[max_len(5)]
+        recipients: Vec<FeeShare>,
+    },
+}
+
+impl AdminChange {
+    /// Seed byte of the change's pending-change PDA.
+    pub fn kind(&self) -> u8 {
+        match self {
+            AdminChange::Fee { .. } => 0,
+            AdminChange::FeeRecipient { .. } => 1,
+            AdminChange::EmergencyWithdraw { .. } => 2,
+            AdminChange::FeeTiers { .. } => 3,
+            AdminChange::CollectionConfig { .. } => 4,
+            AdminChange::RemoveCollectionConfig { .. } => 5,
+            AdminChange::TreasuryRecipients { .. } => 6,
+        }
+    }
+
+    /// Collection whose config the change touches; `Pubkey::default()` for other kinds.
+    pub fn collection(&self) -> Pubkey {
+        match self {
+            AdminChange::CollectionConfig { collection, .. }
+            | AdminChange::RemoveCollectionConfig { collection } => *collection,
+            _ => Pubkey::default(),
+        }
+    }
+
+    /// Role allowed to queue, execute and cancel this change.
+    pub fn role(&self) -> Role {
+        match self {
+            AdminChange::Fee { .. }
+            | AdminChange::FeeTiers { .. }
+            | AdminChange::CollectionConfig { .. }
+            | AdminChange::RemoveCollectionConfig { .. } => Role::FeeManager,
+            AdminChange::FeeRecipient { .. }
+            | AdminChange::EmergencyWithdraw { .. }
+            | AdminChange::TreasuryRecipients { .. } => Role::Treasurer,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            AdminChange::Fee { new_fee_bps } => {
+                require!(*new_fee_bps <= 1000, MarketplaceError::FeeTooHigh);
+            }
+            AdminChange::FeeRecipient { new_fee_recipient } => {
+                require!(
+                    *new_fee_recipient != Pubkey::default(),
+                    MarketplaceError::InvalidFeeRecipient
+                );
+            }
+            AdminChange::EmergencyWithdraw { amount } => {
+                require!(*amount > 0, MarketplaceError::NothingToWithdraw);
+            }
+            AdminChange::FeeTiers { tiers } => {
+                require!(
+                    tiers.len() <= FeeTierTable::MAX_TIERS,
+                    MarketplaceError::TooManyFeeTiers
+                );
+                for tier in tiers {
+                    require!(tier.fee_bps <= 1000, MarketplaceError::FeeTooHigh);
+                }
+                require!(
+                    tiers
+                        .windows(2)
+                        .all(|pair| pair[0].min_fees_paid < pair[1].min_fees_paid),
+                    MarketplaceError::FeeTiersNotSorted
+                );
+            }
+            AdminChange::CollectionConfig { config, .. } => config.validate()?,
+            AdminChange::RemoveCollectionConfig { .. } => {}
+            AdminChange::TreasuryRecipients { recipients } => {
+                Treasury::validate_recipients(recipients)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PendingChange {
+    pub change: AdminChange,
+    pub queued_at: i64,
+    pub executable_at: i64,
+    pub bump: u8,
+}
+
+impl PendingChange {
+    /// Notice given before a queued change can be executed.
+    pub const DELAY: i64 = 2 * 86_400;
+}
+
+/// Progress of a wind-down. The outstanding counts mirror the marketplace's as of the
+/// last return, refund or `complete_wind_down`.
+#[account]
+#[derive(InitSpace)]
+pub struct WindDownReport {
+    pub started_at: i64,
+    pub listings_outstanding: u64,
+    pub offers_outstanding: u64,
+    pub pools_outstanding: u64,
+    pub settlements_outstanding: u64,
+    pub loan_offers_outstanding: u64,
+    /// Loans still running; they can only end by repayment or foreclosure.
+    pub loans_outstanding: u64,
+    pub listings_returned: u64,
+    pub offers_refunded: u64,
+    pub pools_refunded: u64,
+    pub loan_offers_returned: u64,
+    pub lamports_refunded: u64,
+    /// Set by `complete_wind_down` once no escrow remains outstanding.
+    pub completed_at: Option<i64>,
+    pub bump: u8,
+}
+
+impl WindDownReport {
+    fn sync(&mut self, marketplace: &Marketplace) {
+        self.listings_outstanding = marketplace.active_listings;
+        self.offers_outstanding = marketplace.open_offers;
+        self.pools_outstanding = marketplace.bidding_pools;
+        self.settlements_outstanding = marketplace.open_settlements;
+        self.loan_offers_outstanding = marketplace.open_loan_offers;
+        self.loans_outstanding = marketplace.active_loans;
+    }
+}
+
+/// SOL an offerer sets aside to back any number of pool-backed offers. `balance` excludes
+/// the account's rent.
+#[account]
+#[derive(InitSpace)]
+pub struct BiddingPool {
+    pub owner: Pubkey,
+    pub balance: u64,
+    pub bump: u8,
+}
+
+impl BiddingPool {
+    /// Deduct `amount` from the balance; the caller moves the lamports.
+    pub fn debit(&mut self, amount: u64) -> Result<()> {
+        self.balance = self
+            .balance
+            .checked_sub(amount)
+            .ok_or(MarketplaceError::InsufficientPoolBalance)?;
+        Ok(())
+    }
+}
+
+/// How a collaborative work's first sale is divided between its creators. Set by the
+/// mint authority before minting, fixed from then on and shared by every marketplace
+/// instance.
+#[account]
+#[derive(InitSpace)]
+pub struct RevenueSplit {
+    pub nft_mint: Pubkey,
+    pub authority: Pubkey,
+    #[max_len(8)]
+    pub recipients: Vec<FeeShare>,
+    /// Set by the first sale; later sales pay the seller as usual.
+    pub primary_sale_done: bool,
+    pub bump: u8,
+}
+
+impl RevenueSplit {
+    pub const MAX_RECIPIENTS: usize = 8;
+
+    pub fn validate_recipients(recipients: &[FeeShare]) -> Result<()> {
+        require!(
+            !recipients.is_empty() && recipients.len() <= Self::MAX_RECIPIENTS,
+            MarketplaceError::InvalidRevenueSplit
+        );
+        require!(
+            recipients
+                .iter()
+                .all(|share| share.recipient != Pubkey::default()),
+            MarketplaceError::InvalidRevenueSplit
+        );
+        let total_bps: u32 = recipients.iter().map(|share| share.bps as u32).sum();
+        require!(total_bps == 10_000, MarketplaceError::InvalidRevenueSplit);
+        Ok(())
+    }
+
+    /// The split owed on this sale, if `info` holds one still awaiting its first sale,
+    /// which it marks done. Only a sale by the split's authority or one of its recipients
+    /// owes it; anyone else selling the mint first is a later holder and keeps the proceeds.
+    /// `info` is usually uninitialised.
+    pub fn consume(info: &AccountInfo, seller: &Pubkey) -> Result<Option<Self>> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(None);
+        }
+        let mut split = Self::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        if split.primary_sale_done {
+            return Ok(None);
+        }
+        split.primary_sale_done = true;
+        split.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        Ok(split.is_collaborator(seller).then_some(split))
+    }
+
+    fn is_collaborator(&self, wallet: &Pubkey) -> bool {
+        self.authority == *wallet
+            || self
+                .recipients
+                .iter()
+                .any(|share| share.recipient == *wallet)
+    }
+
+    /// Require that no first-sale split is owed on `nft_mint` when `seller` sells it, for
+    /// an account whose address has not been checked yet.
+    pub fn require_settled_at(
+        info: &AccountInfo,
+        nft_mint: &Pubkey,
+        seller: &Pubkey,
+    ) -> Result<()> {
+        let (expected, _) =
+            Pubkey::find_program_address(&[b"revenue_split", nft_mint.as_ref()], &crate::ID);
+        require_keys_eq!(info.key(), expected, MarketplaceError::InvalidBatchAccounts);
+        require!(
+            Self::consume(info, seller)?.is_none(),
+            MarketplaceError::RevenueSplitPending
+        );
+        Ok(())
+    }
+
+    /// Each recipient's share of `total`. Rounding dust goes to the first recipient.
+    pub fn shares_of(&self, total: u64) -> Result<Vec<(Pubkey, u64)>> {
+        let mut shares = self
+            .recipients
+            .iter()
+            .map(|share| Ok((share.recipient, share.portion_of(total)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let paid: u64 = shares.iter().map(|(_, amount)| amount).sum();
+        if let Some((_, first)) = shares.first_mut() {
+            *first += total - paid;
+        }
+        Ok(shares)
+    }
+}
+
+/// Seller proceeds of a held sale, open to dispute by the buyer until `release_at`.
+/// `amount` excludes the account's rent, which returns to `rent_payer`.
+#[account]
+#[derive(InitSpace)]
+pub struct Settlement {
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub nft_mint: Pubkey,
+    pub rent_payer: Pubkey,
+    pub amount: u64,
+    pub release_at: i64,
+    pub disputed: bool,
+    /// The proceeds are owed to the mint's revenue split rather than the seller.
+    pub split: bool,
+    pub bump: u8,
+}
+
+impl Settlement {
+    /// Hold `amount` of `listing`'s proceeds from its sale to `buyer` for the listing's
+    /// hold period. `split` marks proceeds owed to the mint's revenue split.
+    pub fn hold(
+        &mut self,
+        listing: &Listing,
+        buyer: Pubkey,
+        rent_payer: Pubkey,
+        amount: u64,
+        split: bool,
+        bump: Option<u8>,
+    ) -> Result<()> {
+        self.buyer = buyer;
+        self.seller = listing.seller;
+        self.nft_mint = listing.nft_mint;
+        self.rent_payer = rent_payer;
+        self.amount = amount;
+        self.release_at = Clock::get()?
+            .unix_timestamp
+            .checked_add(listing.hold_period)
+            .ok_or(MarketplaceError::Overflow)?;
+        self.disputed = false;
+        self.split = split;
+        self.bump = bump.ok_or(MarketplaceError::SettlementMismatch)?;
+
+        emit!(SettlementOpened {
+            nft_mint: self.nft_mint,
+            buyer,
+            seller: self.seller,
+            amount,
+            release_at: self.release_at,
+        });
+        Ok(())
+    }
+
+    /// Mark the settlement disputed; only possible once and before `release_at`.
+    pub fn open_dispute(&mut self, now: i64) -> Result<()> {
+        require!(!self.disputed, MarketplaceError::SettlementDisputed);
+        require!(now < self.release_at, MarketplaceError::DisputeWindowClosed);
+        self.disputed = true;
+        Ok(())
+    }
+
+    /// Require the proceeds to be undisputed and past their hold period.
+    pub fn require_releasable(&self, now: i64) -> Result<()> {
+        require!(!self.disputed, MarketplaceError::SettlementDisputed);
+        require!(now >= self.release_at, MarketplaceError::SettlementLocked);
+        Ok(())
+    }
+
+    /// Proceeds left for the seller after refunding `refund_amount` of a disputed sale.
+    pub fn released_after_refund(&self, refund_amount: u64) -> Result<u64> {
+        require!(self.disputed, MarketplaceError::SettlementNotDisputed);
+        Ok(self
+            .amount
+            .checked_sub(refund_amount)
+            .ok_or(MarketplaceError::InvalidRefundAmount)?)
+    }
+
+    /// Pay `amount` of the held proceeds to the seller, or to the revenue split's
+    /// `recipients` when the sale owed one.
+    pub fn release<'info>(
+        settlement: &Account<'info, Self>,
+        amount: u64,
+        seller: &AccountInfo<'info>,
+        revenue_split: Option<&RevenueSplit>,
+        recipients: &[AccountInfo<'info>],
+        instance_seed: &[u8],
+        system_program: &AccountInfo<'info>,
+    ) -> Result<()> {
+        let from = settlement.to_account_info();
+        if !settlement.split {
+            return send_lamports(&from, seller, system_program, amount);
+        }
+        let split = revenue_split.ok_or(MarketplaceError::InvalidRevenueSplit)?;
+        pay_split(
+            &from,
+            recipients,
+            &split.shares_of(amount)?,
+            instance_seed,
+            system_program,
+            settlement.nft_mint,
+        )
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, InitSpace)]
+pub struct Offer {
+    pub offerer: Pubkey,
+    pub nft_mint: Pubkey,
+    pub amount: u64,
+    pub expiration_time: i64,
+    pub is_active: bool,
+    pub created_at: i64,
+    pub bump: u8,
+    /// Layout version; 0 for accounts still in the `legacy` layout.
+    pub version: u8,
+    /// Funded from the offerer's `BiddingPool` at fill time rather than an `OfferEscrow`.
+    pub pool_backed: bool,
+    /// Included in the marketplace's `open_offers`. False for legacy offers until
+    /// `migrate_offer` counts them.
+    pub counted: bool,
+    /// Zeroed space for fields added by later versions.
+    pub reserved: [u8; 62],
+}
+
+versioned_account!(Offer);
+
+impl Offer {
+    pub const VERSION: u8 = 1;
+
+    fn from_legacy(legacy: legacy::Offer) -> Self {
+        Self {
+            offerer: legacy.offerer,
+            nft_mint: legacy.nft_mint,
+            amount: legacy.amount,
+            expiration_time: legacy.expiration_time,
+            is_active: legacy.is_active,
+            created_at: legacy.created_at,
+            bump: legacy.bump,
+            version: 0,
+            pool_backed: false,
+            counted: false,
+            reserved: [0; 62],
+        }
+    }
+
+    /// Legacy layout of this account, or `None` if a field it cannot hold changed.
+    fn to_legacy(&self) -> Option<legacy::Offer> {
+        let legacy = legacy::Offer {
+            offerer: self.offerer,
+            nft_mint: self.nft_mint,
+            amount: self.amount,
+            expiration_time: self.expiration_time,
+            is_active: self.is_active,
+            created_at: self.created_at,
+            bump: self.bump,
+        };
+        (Self::from_legacy(legacy.clone()) == *self).then_some(legacy)
+    }
+
+    /// (Re)initialise this offer as active and funded by its `OfferEscrow`.
+    pub fn open(
+        &mut self,
+        offerer: Pubkey,
+        nft_mint: Pubkey,
+        amount: u64,
+        duration: i64,
+        bump: u8,
+        now: i64,
+    ) -> Result<()> {
+        self.offerer = offerer;
+        self.nft_mint = nft_mint;
+        self.amount = amount;
+        self.expiration_time = now
+            .checked_add(duration)
+            .ok_or(MarketplaceError::Overflow)?;
+        self.is_active = true;
+        self.counted = true;
+        self.created_at = now;
+        self.bump = bump;
+        self.version = Self::VERSION;
+        self.pool_backed = false;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Escrow {
+    pub nft_mint: Pubkey,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct OfferEscrow {
+    pub nft_mint: Pubkey,
+    pub offerer: Pubkey,
+    pub bump: u8,
+}
+
+/// A lender's standing offer to lend against any NFT of a verified collection.
+/// The principal is held in this account's lamports until a borrower takes it.
+#[account]
+#[derive(InitSpace)]
+pub struct LoanOffer {
+    pub lender: Pubkey,
+    pub collection: Pubkey,
+    pub principal: u64,
+    pub apr_bps: u16,
+    pub duration: i64,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Loan {
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub nft_mint: Pubkey,
+    pub collection: Pubkey,
+    pub principal: u64,
+    pub apr_bps: u16,
+    pub start_time: i64,
+    pub due_time: i64,
+    pub bump: u8,
+}
+
+impl Loan {
+    const SECONDS_PER_YEAR: u128 = 365 * 86400;
+
+    /// Simple interest accrued from `start_time` to `now`, capped at the loan term.
+    pub fn interest_due(&self, now: i64) -> Result<u64> {
+        let elapsed = now
+            .min(self.due_time)
+            .saturating_sub(self.start_time)
+            .max(0) as u128;
+        let interest = (self.principal as u128)
+            .checked_mul(self.apr_bps as u128)
+            .and_then(|v| v.checked_mul(elapsed))
+            .ok_or(MarketplaceError::Overflow)?
+            / (10_000 * Self::SECONDS_PER_YEAR);
+        u64::try_from(interest).map_err(|_| MarketplaceError::Overflow.into())
+    }
+}
+
+// ─── Events ──────────────────────────────────────────────────────────────────
+
+#[event]
+pub struct MarketplaceInitialized {
+    pub instance: Pubkey,
+    pub admin: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub fee_bps: u16,
+}
+
+#[event]
+pub struct ListingCreated {
+    pub seller: Pubkey,
+    pub nft_mint: Pubkey,
+    pub price: u64,
+    pub is_auction: bool,
+    pub is_custodial: bool,
+    pub start_time: Option<i64>,
+    pub hold_period: i64,
+    pub expiration_time: i64,
+}
+
+#[event]
+pub struct ListingCancelled {
+    pub nft_mint: Pubkey,
+    pub seller: Pubkey,
+}
+
+#[event]
+pub struct NftPurchased {
+    pub nft_mint: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub price: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct SweepCompleted {
+    pub buyer: Pubkey,
+    pub requested: u32,
+    pub purchased: u32,
+    pub total_price: u64,
+}
+
+#[event]
+pub struct OfferCreated {
+    pub nft_mint: Pubkey,
+    pub offerer: Pubkey,
+    pub amount: u64,
+    pub expiration_time: i64,
+    pub pool_backed: bool,
+}
+
+#[event]
+pub struct OfferCancelled {
+    pub nft_mint: Pubkey,
+    pub offerer: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct OfferAccepted {
+    pub nft_mint: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub price: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct PriceUpdated {
+    pub nft_mint: Pubkey,
+    pub old_price: u64,
+    pub new_price: u64,
+    pub revision: u64,
+}
+
+#[event]
+pub struct ListingUpdated {
+    pub nft_mint: Pubkey,
+    pub price: u64,
+    pub expiration_time: i64,
+    pub is_auction: bool,
+    pub reserved_buyer: Pubkey,
+    pub revision: u64,
+}
+
+#[event]
+pub struct AutoAcceptPriceUpdated {
+    pub nft_mint: Pubkey,
+    pub auto_accept_price: Option<u64>,
+    pub revision: u64,
+}
+
+#[event]
+pub struct BatchProcessed {
+    pub seller: Pubkey,
+    pub requested: u32,
+    pub processed: u32,
+}
+
+#[event]
+pub struct FeeTiersUpdated {
+    pub tiers: Vec<FeeTier>,
+}
+
+#[event]
+pub struct TreasuryRecipientsUpdated {
+    pub recipients: Vec<FeeShare>,
+}
+
+#[event]
+pub struct FeesDistributed {
+    /// Token mint distributed, or the default pubkey for SOL.
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ReferrerUpdated {
+    pub wallet: Pubkey,
+    pub share_bps: u16,
+}
+
+#[event]
+pub struct ReferrerRemoved {
+    pub wallet: Pubkey,
 }
 
-#[derive(Accounts)]
-pub struct CancelListing<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
+#[event]
+pub struct ReferralPaid {
+    pub referrer: Pubkey,
+    pub nft_mint: Pubkey,
+    pub amount: u64,
+}
 
-    #[account(
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+#[event]
+pub struct CollectionConfigUpdated {
+    pub collection: Pubkey,
+    pub config: CollectionConfigArgs,
+}
 
-    pub nft_mint: Account<'info, Mint>,
+#[event]
+pub struct CollectionConfigRemoved {
+    pub collection: Pubkey,
+}
 
-    #[account(
-        mut,
-        seeds = [b"listing", nft_mint.key().as_ref()],
-        bump = listing.bump,
-        close = authority,
-    )]
-    pub listing: Account<'info, Listing>,
+#[event]
+pub struct SettlementOpened {
+    pub nft_mint: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub amount: u64,
+    pub release_at: i64,
+}
 
-    #[account(
-        seeds = [b"escrow", nft_mint.key().as_ref()],
-        bump = escrow.bump,
-    )]
-    pub escrow: Account<'info, Escrow>,
+#[event]
+pub struct DisputeOpened {
+    pub nft_mint: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub evidence: [u8; 32],
+}
 
-    #[account(
-        mut,
-        associated_token::mint = nft_mint,
-        associated_token::authority = escrow,
-    )]
-    pub escrow_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct SettlementReleased {
+    pub nft_mint: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub amount: u64,
+}
 
-    #[account(
-        mut,
-        associated_token::mint = nft_mint,
-        associated_token::authority = listing.seller,
-    )]
-    pub seller_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct DisputeResolved {
+    pub nft_mint: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub refunded: u64,
+    pub released: u64,
+    pub nft_recipient: Pubkey,
+}
 
-    pub token_program: Program<'info, Token>,
+#[event]
+pub struct ConsignmentAgentUpdated {
+    pub nft_mint: Pubkey,
+    pub agent: Option<ConsignmentAgent>,
+    pub revision: u64,
 }
 
-#[derive(Accounts)]
-pub struct BuyNft<'info> {
-    #[account(mut)]
-    pub buyer: Signer<'info>,
+#[event]
+pub struct CommissionPaid {
+    pub agent: Pubkey,
+    pub nft_mint: Pubkey,
+    pub amount: u64,
+}
 
-    /// CHECK: Seller receives SOL payment — validated against listing.seller
-    #[account(mut, constraint = seller.key() == listing.seller @ MarketplaceError::InvalidSeller)]
-    pub seller: UncheckedAccount<'info>,
+#[event]
+pub struct RevenueSplitUpdated {
+    pub nft_mint: Pubkey,
+    pub recipients: Vec<FeeShare>,
+}
 
-    #[account(
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+#[event]
+pub struct RevenueSplitPaid {
+    pub recipient: Pubkey,
+    pub nft_mint: Pubkey,
+    pub amount: u64,
+}
 
-    /// CHECK: Fee recipient — validated against marketplace.fee_recipient
-    #[account(mut, constraint = fee_recipient.key() == marketplace.fee_recipient @ MarketplaceError::InvalidFeeRecipient)]
-    pub fee_recipient: UncheckedAccount<'info>,
+#[event]
+pub struct RoyaltyPaid {
+    pub creator: Pubkey,
+    pub nft_mint: Pubkey,
+    pub amount: u64,
+}
 
-    pub nft_mint: Account<'info, Mint>,
+#[event]
+pub struct KeyBlocklisted {
+    pub key: Pubkey,
+    pub reason: u16,
+    pub flagged_by: Pubkey,
+}
 
-    #[account(
-        mut,
-        seeds = [b"listing", nft_mint.key().as_ref()],
-        bump = listing.bump,
-    )]
-    pub listing: Account<'info, Listing>,
+#[event]
+pub struct KeyUnblocklisted {
+    pub key: Pubkey,
+}
 
-    #[account(
-        seeds = [b"escrow", nft_mint.key().as_ref()],
-        bump = escrow.bump,
-    )]
-    pub escrow: Account<'info, Escrow>,
+/// Emitted just before an instruction touching a blocklisted key fails.
+#[event]
+pub struct BlockedActivity {
+    pub key: Pubkey,
+    pub reason: u16,
+}
 
-    #[account(
-        mut,
-        associated_token::mint = nft_mint,
-        associated_token::authority = escrow,
-    )]
-    pub escrow_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct AdminTransferProposed {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+}
 
-    #[account(
-        init_if_needed,
-        payer = buyer,
-        associated_token::mint = nft_mint,
-        associated_token::authority = buyer,
-    )]
-    pub buyer_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct AdminTransferred {
+    pub old_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
 
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct AdminRenounced {
+    pub admin: Pubkey,
 }
 
-#[derive(Accounts)]
-pub struct MakeOffer<'info> {
-    #[account(mut)]
-    pub offerer: Signer<'info>,
+#[event]
+pub struct RoleGranted {
+    pub role: Role,
+    pub holder: Pubkey,
+}
 
-    #[account(
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+#[event]
+pub struct RoleRevoked {
+    pub role: Role,
+    pub holder: Pubkey,
+}
 
-    pub nft_mint: Account<'info, Mint>,
+#[event]
+pub struct MarketplacePausedEvent {
+    pub paused: u8,
+}
 
-    #[account(
-        seeds = [b"listing", nft_mint.key().as_ref()],
-        bump = listing.bump,
-    )]
-    pub listing: Account<'info, Listing>,
+#[event]
+pub struct FeeChangeQueued {
+    pub change: AdminChange,
+    pub executable_at: i64,
+}
 
-    #[account(
-        init_if_needed,
-        payer = offerer,
-        space = 8 + Offer::INIT_SPACE,
-        seeds = [b"offer", nft_mint.key().as_ref(), offerer.key().as_ref()],
-        bump,
-    )]
-    pub offer: Account<'info, Offer>,
+#[event]
+pub struct FeeChangeCancelled {
+    pub change: AdminChange,
+}
 
-    #[account(
-        init_if_needed,
-        payer = offerer,
-        space = 8 + OfferEscrow::INIT_SPACE,
-        seeds = [b"offer_escrow", nft_mint.key().as_ref(), offerer.key().as_ref()],
-        bump,
-    )]
-    pub offer_escrow: Account<'info, OfferEscrow>,
+#[event]
+pub struct FeeUpdated {
+    pub old_fee_bps: u16,
+    pub new_fee_bps: u16,
+}
 
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct FeeRecipientUpdated {
+    pub old_recipient: Pubkey,
+    pub new_recipient: Pubkey,
 }
 
-#[derive(Accounts)]
-pub struct CancelOffer<'info> {
-    #[account(mut)]
-    pub offerer: Signer<'info>,
+#[event]
+pub struct LoanOfferCreated {
+    pub lender: Pubkey,
+    pub collection: Pubkey,
+    pub principal: u64,
+    pub apr_bps: u16,
+    pub duration: i64,
+}
 
-    pub nft_mint: Account<'info, Mint>,
+#[event]
+pub struct LoanOfferCancelled {
+    pub lender: Pubkey,
+    pub collection: Pubkey,
+    pub principal: u64,
+}
 
-    #[account(
-        mut,
-        seeds = [b"offer", nft_mint.key().as_ref(), offerer.key().as_ref()],
-        bump = offer.bump,
-        close = offerer,
-    )]
-    pub offer: Account<'info, Offer>,
+#[event]
+pub struct LoanStarted {
+    pub nft_mint: Pubkey,
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub principal: u64,
+    pub apr_bps: u16,
+    pub due_time: i64,
+}
 
-    #[account(
-        mut,
-        seeds = [b"offer_escrow", nft_mint.key().as_ref(), offerer.key().as_ref()],
-        bump = offer_escrow.bump,
-        close = offerer,
-    )]
-    pub offer_escrow: Account<'info, OfferEscrow>,
+#[event]
+pub struct LoanRepaid {
+    pub nft_mint: Pubkey,
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub principal: u64,
+    pub interest: u64,
+}
+
+#[event]
+pub struct LoanForeclosed {
+    pub nft_mint: Pubkey,
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub principal: u64,
+}
+
+#[event]
+pub struct WindDownStarted {
+    pub listings_outstanding: u64,
+    pub offers_outstanding: u64,
+    pub pools_outstanding: u64,
+    pub settlements_outstanding: u64,
+    pub loan_offers_outstanding: u64,
+    pub loans_outstanding: u64,
+}
+
+#[event]
+pub struct ListingReturned {
+    pub nft_mint: Pubkey,
+    pub seller: Pubkey,
+}
+
+#[event]
+pub struct OfferRefunded {
+    pub nft_mint: Pubkey,
+    pub offerer: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct WindDownCompleted {
+    pub listings_returned: u64,
+    pub offers_refunded: u64,
+    pub pools_refunded: u64,
+    pub loan_offers_returned: u64,
+    pub lamports_refunded: u64,
+}
+
+#[event]
+pub struct BiddingPoolUpdated {
+    pub owner: Pubkey,
+    pub balance: u64,
+}
+
+#[event]
+pub struct BiddingPoolClosed {
+    pub owner: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct LoanOfferReturned {
+    pub lender: Pubkey,
+    pub collection: Pubkey,
+    pub principal: u64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub account: Pubkey,
+    pub version: u8,
+}
+
+// ─── Fees ────────────────────────────────────────────────────────────────────
+
+/// Marketplace fee owed on a sale of `price` lamports at `fee_bps`.
+pub fn marketplace_fee(price: u64, fee_bps: u16) -> Result<u64> {
+    Ok(price
+        .checked_mul(fee_bps as u64)
+        .ok_or(MarketplaceError::Overflow)?
+        / 10_000)
+}
+
+/// How a sale price is divided between the seller, the marketplace, a referrer and
+/// the NFT's creators.
+pub struct SalePayout {
+    pub seller_amount: u64,
+    pub marketplace_fee: u64,
+    pub referral_fee: u64,
+    /// Royalty owed to each creator, in metadata creator order.
+    pub royalties: Vec<(Pubkey, u64)>,
+    /// Consignment agent's wallet and commission, taken from the seller's share.
+    pub commission: Option<(Pubkey, u64)>,
+    /// Collaborators' shares of a first sale's proceeds, paid in place of the seller.
+    pub split: Vec<(Pubkey, u64)>,
+}
+
+impl SalePayout {
+    pub fn new(
+        price: u64,
+        fee_bps: u16,
+        referrer: Option<&Referrer>,
+        royalties: Vec<(Pubkey, u64)>,
+    ) -> Result<Self> {
+        let fee = marketplace_fee(price, fee_bps)?;
+        let referral_fee = match referrer {
+            Some(referrer) => marketplace_fee(fee, referrer.share_bps)?,
+            None => 0,
+        };
+        let royalty_total = royalties
+            .iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .ok_or(MarketplaceError::Overflow)?;
+        Ok(Self {
+            seller_amount: price
+                .checked_sub(fee)
+                .and_then(|rest| rest.checked_sub(royalty_total))
+                .ok_or(MarketplaceError::Overflow)?,
+            marketplace_fee: fee - referral_fee,
+            referral_fee,
+            royalties,
+            commission: None,
+            split: Vec::new(),
+        })
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    /// Apply the first-sale split `revenue_split` owes on a sale by `seller`, if any. A held
+    /// sale keeps the seller's share whole in its settlement and pays the split on release;
+    /// returns whether it owes one.
+    pub fn apply_split(
+        &mut self,
+        revenue_split: &AccountInfo,
+        seller: &Pubkey,
+        held: bool,
+    ) -> Result<bool> {
+        match RevenueSplit::consume(revenue_split, seller)? {
+            Some(split) if !held => {
+                self.split_proceeds(&split)?;
+                Ok(false)
+            }
+            split => Ok(split.is_some()),
+        }
+    }
 
-#[derive(Accounts)]
-pub struct AcceptOffer<'info> {
-    #[account(mut)]
-    pub seller: Signer<'info>,
+    /// Pay the seller's share to the collaborators of `split` instead.
+    pub fn split_proceeds(&mut self, split: &RevenueSplit) -> Result<()> {
+        self.split = split.shares_of(self.seller_amount)?;
+        self.seller_amount = 0;
+        Ok(())
+    }
 
-    /// CHECK: Offerer (buyer) — validated against offer.offerer
-    pub offerer: UncheckedAccount<'info>,
+    /// Divide a sale's remaining accounts into the royalty creators and the revenue
+    /// split recipients after them.
+    pub fn partition_accounts<'a, 'info>(
+        &self,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> (&'a [AccountInfo<'info>], &'a [AccountInfo<'info>]) {
+        accounts.split_at((2 * self.royalties.len()).min(accounts.len()))
+    }
 
-    #[account(
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+    /// Pay `commission_bps` of `price` to `agent` out of the seller's share.
+    pub fn add_commission(&mut self, agent: Pubkey, price: u64, commission_bps: u16) -> Result<()> {
+        let amount = marketplace_fee(price, commission_bps)?;
+        self.seller_amount = self
+            .seller_amount
+            .checked_sub(amount)
+            .ok_or(MarketplaceError::InvalidCommission)?;
+        self.commission = Some((agent, amount));
+        Ok(())
+    }
 
-    /// CHECK: Fee recipient — validated against marketplace.fee_recipient
-    #[account(mut, constraint = fee_recipient.key() == marketplace.fee_recipient @ MarketplaceError::InvalidFeeRecipient)]
-    pub fee_recipient: UncheckedAccount<'info>,
+    /// The full fee taken from the sale, including the referrer's share.
+    pub fn total_fee(&self) -> u64 {
+        self.marketplace_fee + self.referral_fee
+    }
+}
 
-    pub nft_mint: Account<'info, Mint>,
+/// The accounts that decide a sale's fee rate, borrowed from a sale context.
+pub struct SaleFees<'a> {
+    pub marketplace: &'a Marketplace,
+    pub fee_tiers: Option<FeeTierTable>,
+    pub seller_stats: Option<SellerStats>,
+    pub referrer: Option<&'a Referrer>,
+}
 
-    #[account(
-        mut,
-        seeds = [b"listing", nft_mint.key().as_ref()],
-        bump = listing.bump,
-    )]
-    pub listing: Account<'info, Listing>,
+impl SaleFees<'_> {
+    /// Split `price` for a sale, applying the collection's overrides if any.
+    pub fn payout(&self, price: u64, collection: Option<&CollectionMatch>) -> Result<SalePayout> {
+        let base_fee_bps = collection
+            .and_then(|collection| collection.config.fee_bps)
+            .unwrap_or(self.marketplace.fee_bps);
+        let fee_bps = seller_fee_bps(
+            base_fee_bps,
+            self.fee_tiers.as_ref(),
+            self.seller_stats.as_ref(),
+        );
+        let royalties = match collection {
+            Some(collection) => collection.royalties(price)?,
+            None => Vec::new(),
+        };
+        SalePayout::new(price, fee_bps, self.referrer, royalties)
+    }
+}
 
-    #[account(
-        seeds = [b"escrow", nft_mint.key().as_ref()],
-        bump = escrow.bump,
-    )]
-    pub escrow: Account<'info, Escrow>,
+/// Where a sale's proceeds go.
+pub struct SalePayees<'a, 'info> {
+    pub seller: &'a AccountInfo<'info>,
+    pub fee_recipient: &'a AccountInfo<'info>,
+    pub referrer: Option<&'a mut Account<'info, Referrer>>,
+    pub referrer_wallet: Option<&'a AccountInfo<'info>>,
+    /// Blocklist PDA of `referrer_wallet`.
+    pub referrer_blocklist: Option<&'a AccountInfo<'info>>,
+    /// Seed of the instance whose blocklist the payees are checked against.
+    pub instance_seed: &'a [u8],
+    /// The NFT's metadata creators, in order, each followed by its blocklist PDA; empty
+    /// unless royalties are owed.
+    pub creators: &'a [AccountInfo<'info>],
+    /// The revenue split's recipients, in order, each followed by its blocklist PDA; empty
+    /// unless the payout is split.
+    pub split_recipients: &'a [AccountInfo<'info>],
+    /// Consignment agent's wallet; required when the payout includes a commission.
+    pub agent_wallet: Option<&'a AccountInfo<'info>>,
+    pub system_program: &'a AccountInfo<'info>,
+}
 
-    #[account(
-        mut,
-        associated_token::mint = nft_mint,
-        associated_token::authority = escrow,
-    )]
-    pub escrow_token_account: Account<'info, TokenAccount>,
+impl<'a, 'info> SalePayees<'a, 'info> {
+    /// Pay every share of `payout` out of `from`.
+    pub fn pay(
+        self,
+        payout: &SalePayout,
+        from: &AccountInfo<'info>,
+        nft_mint: Pubkey,
+    ) -> Result<()> {
+        require!(
+            self.creators.len() == 2 * payout.royalties.len(),
+            MarketplaceError::InvalidCreator
+        );
 
-    #[account(
-        init_if_needed,
-        payer = seller,
-        associated_token::mint = nft_mint,
-        associated_token::authority = offerer,
-    )]
-    pub offerer_token_account: Account<'info, TokenAccount>,
+        send_lamports(from, self.seller, self.system_program, payout.seller_amount)?;
+        send_lamports(
+            from,
+            self.fee_recipient,
+            self.system_program,
+            payout.marketplace_fee,
+        )?;
+        if let Some(referrer) = self.referrer.as_deref() {
+            let blocklist = self
+                .referrer_blocklist
+                .ok_or(MarketplaceError::InvalidReferrer)?;
+            BlocklistEntry::require_clear_at(blocklist, self.instance_seed, &referrer.wallet)?;
+        }
+        pay_referral(
+            self.referrer,
+            self.referrer_wallet,
+            from,
+            self.system_program,
+            payout.referral_fee,
+            nft_mint,
+        )?;
 
-    #[account(
-        mut,
-        seeds = [b"offer", nft_mint.key().as_ref(), offerer.key().as_ref()],
-        bump = offer.bump,
-        close = seller,
-    )]
-    pub offer: Account<'info, Offer>,
+        for (pair, (address, amount)) in self.creators.chunks(2).zip(&payout.royalties) {
+            let [creator, blocklist] = pair else {
+                return err!(MarketplaceError::InvalidCreator);
+            };
+            require_keys_eq!(creator.key(), *address, MarketplaceError::InvalidCreator);
+            BlocklistEntry::require_clear_at(blocklist, self.instance_seed, address)?;
+            send_lamports(from, creator, self.system_program, *amount)?;
+            emit!(RoyaltyPaid {
+                creator: *address,
+                nft_mint,
+                amount: *amount,
+            });
+        }
 
-    #[account(
-        mut,
-        seeds = [b"offer_escrow", nft_mint.key().as_ref(), offerer.key().as_ref()],
-        bump = offer_escrow.bump,
-        close = seller,
-    )]
-    pub offer_escrow: Account<'info, OfferEscrow>,
+        pay_split(
+            from,
+            self.split_recipients,
+            &payout.split,
+            self.instance_seed,
+            self.system_program,
+            nft_mint,
+        )?;
 
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
-}
+        if let Some((agent, amount)) = payout.commission {
+            let wallet = self.agent_wallet.ok_or(MarketplaceError::InvalidAgent)?;
+            require_keys_eq!(wallet.key(), agent, MarketplaceError::InvalidAgent);
+            send_lamports(from, wallet, self.system_program, amount)?;
+            emit!(CommissionPaid {
+                agent,
+                nft_mint,
+                amount,
+            });
+        }
 
-#[derive(Accounts)]
-pub struct UpdatePrice<'info> {
-    pub seller: Signer<'info>,
+        Ok(())
+    }
+}
 
-    pub nft_mint: Account<'info, Mint>,
+/// Pay each revenue split share out of `from` to `recipients`, passed in the split's order
+/// and each followed by its blocklist PDA in the instance with `instance_seed`.
+fn pay_split<'info>(
+    from: &AccountInfo<'info>,
+    recipients: &[AccountInfo<'info>],
+    shares: &[(Pubkey, u64)],
+    instance_seed: &[u8],
+    system_program: &AccountInfo<'info>,
+    nft_mint: Pubkey,
+) -> Result<()> {
+    require!(
+        recipients.len() == 2 * shares.len(),
+        MarketplaceError::InvalidRevenueSplit
+    );
+    for (pair, (address, amount)) in recipients.chunks(2).zip(shares) {
+        let [recipient, blocklist] = pair else {
+            return err!(MarketplaceError::InvalidRevenueSplit);
+        };
+        require_keys_eq!(
+            recipient.key(),
+            *address,
+            MarketplaceError::InvalidRevenueSplit
+        );
+        BlocklistEntry::require_clear_at(blocklist, instance_seed, address)?;
+        send_lamports(from, recipient, system_program, *amount)?;
+        emit!(RevenueSplitPaid {
+            recipient: *address,
+            nft_mint,
+            amount: *amount,
+        });
+    }
+    Ok(())
+}
 
-    #[account(
-        mut,
-        seeds = [b"listing", nft_mint.key().as_ref()],
-        bump = listing.bump,
-    )]
-    pub listing: Account<'info, Listing>,
+/// Move lamports out of `from`. Program-owned escrows are debited directly; any
+/// other source must be a signer and pays through the system program.
+fn send_lamports<'info>(
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    if from.owner == &crate::ID {
+        **from.try_borrow_mut_lamports()? -= amount;
+        **to.try_borrow_mut_lamports()? += amount;
+        return Ok(());
+    }
+    anchor_lang::system_program::transfer(
+        CpiContext::new(
+            system_program.clone(),
+            anchor_lang::system_program::Transfer {
+                from: from.clone(),
+                to: to.clone(),
+            },
+        ),
+        amount,
+    )
 }
 
-#[derive(Accounts)]
-pub struct PauseMarketplace<'info> {
-    pub admin: Signer<'info>,
+/// Pay a referral fee to the referrer's wallet, if a referrer was supplied.
+fn pay_referral<'info>(
+    referrer: Option<&mut Account<'info, Referrer>>,
+    referrer_wallet: Option<&AccountInfo<'info>>,
+    from: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+    nft_mint: Pubkey,
+) -> Result<()> {
+    let Some(referrer) = referrer else {
+        return Ok(());
+    };
+    let wallet = referrer_wallet.ok_or(MarketplaceError::InvalidReferrer)?;
+    require_keys_eq!(
+        wallet.key(),
+        referrer.wallet,
+        MarketplaceError::InvalidReferrer
+    );
+
+    send_lamports(from, wallet, system_program, amount)?;
+    referrer.total_earned = referrer
+        .total_earned
+        .checked_add(amount)
+        .ok_or(MarketplaceError::Overflow)?;
+
+    emit!(ReferralPaid {
+        referrer: referrer.wallet,
+        nft_mint,
+        amount,
+    });
+
+    Ok(())
+}
 
-    #[account(
-        mut,
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+/// The fee rate for a seller, from the highest fee tier their recorded fees reach.
+pub fn seller_fee_bps(
+    base_fee_bps: u16,
+    fee_tiers: Option<&FeeTierTable>,
+    seller_stats: Option<&SellerStats>,
+) -> u16 {
+    fee_tiers
+        .zip(seller_stats)
+        .and_then(|(table, stats)| table.fee_bps_for(stats.fees_paid))
+        .unwrap_or(base_fee_bps)
 }
 
-#[derive(Accounts)]
-pub struct UnpauseMarketplace<'info> {
-    pub admin: Signer<'info>,
+// ─── Metadata ────────────────────────────────────────────────────────────────
 
-    #[account(
-        mut,
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+pub const TOKEN_METADATA_PROGRAM_ID: Pubkey =
+    pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+#[derive(AnchorDeserialize)]
+pub struct MetadataCreator {
+    pub address: Pubkey,
+    pub verified: bool,
+    pub share: u8,
 }
 
-#[derive(Accounts)]
-pub struct UpdateFee<'info> {
-    pub admin: Signer<'info>,
+#[derive(AnchorDeserialize)]
+pub struct MetadataCollection {
+    pub verified: bool,
+    pub key: Pubkey,
+}
 
-    #[account(
-        mut,
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+/// Leading fields of a Metaplex `Metadata` account, up to and including `collection`.
+/// Trailing fields are not needed by the marketplace and are left unread.
+#[derive(AnchorDeserialize)]
+pub struct MetadataPrefix {
+    pub key: u8,
+    pub update_authority: Pubkey,
+    pub mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub creators: Option<Vec<MetadataCreator>>,
+    pub primary_sale_happened: bool,
+    pub is_mutable: bool,
+    pub edition_nonce: Option<u8>,
+    pub token_standard: Option<u8>,
+    pub collection: Option<MetadataCollection>,
 }
 
-#[derive(Accounts)]
-pub struct UpdateFeeRecipient<'info> {
-    pub admin: Signer<'info>,
+impl MetadataPrefix {
+    const PROGRAMMABLE_NON_FUNGIBLE: u8 = 4;
+    const PROGRAMMABLE_NON_FUNGIBLE_EDITION: u8 = 5;
 
-    #[account(
-        mut,
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+    /// Deserialize the metadata account for `mint`, checking its owner and PDA address.
+    pub fn load(metadata: &AccountInfo, mint: &Pubkey) -> Result<Self> {
+        require_keys_eq!(
+            *metadata.owner,
+            TOKEN_METADATA_PROGRAM_ID,
+            MarketplaceError::InvalidMetadata
+        );
+        let (expected, _) = Pubkey::find_program_address(
+            &[
+                b"metadata",
+                TOKEN_METADATA_PROGRAM_ID.as_ref(),
+                mint.as_ref(),
+            ],
+            &TOKEN_METADATA_PROGRAM_ID,
+        );
+        require_keys_eq!(metadata.key(), expected, MarketplaceError::InvalidMetadata);
 
-    /// CHECK: New fee recipient
-    pub new_fee_recipient: UncheckedAccount<'info>,
-}
+        let data = metadata.try_borrow_data()?;
+        let parsed = Self::deserialize(&mut &data[..])
+            .map_err(|_| error!(MarketplaceError::InvalidMetadata))?;
+        require_keys_eq!(parsed.mint, *mint, MarketplaceError::InvalidMetadata);
+        Ok(parsed)
+    }
 
-#[derive(Accounts)]
-pub struct EmergencyWithdraw<'info> {
-    #[account(mut)]
-    pub admin: Signer<'info>,
+    /// Whether the NFT is programmable, whose transfers go through token-metadata rules.
+    pub fn is_programmable(&self) -> bool {
+        matches!(
+            self.token_standard,
+            Some(Self::PROGRAMMABLE_NON_FUNGIBLE | Self::PROGRAMMABLE_NON_FUNGIBLE_EDITION)
+        )
+    }
 
-    #[account(
-        mut,
-        seeds = [b"marketplace"],
-        bump = marketplace.bump,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+    /// The collection key, if the metadata carries a verified collection.
+    pub fn verified_collection(&self) -> Option<Pubkey> {
+        self.collection
+            .as_ref()
+            .filter(|collection| collection.verified)
+            .map(|collection| collection.key)
+    }
 }
 
-// ─── Account Data ────────────────────────────────────────────────────────────
+/// A collection config together with the metadata of the NFT it applies to.
+pub struct CollectionMatch {
+    pub config: CollectionConfig,
+    pub metadata: MetadataPrefix,
+}
 
-#[account]
-#[derive(InitSpace)]
-pub struct Marketplace {
-    pub admin: Pubkey,
-    pub fee_recipient: Pubkey,
-    pub fee_bps: u16,
-    pub paused: bool,
-    pub listing_count: u64,
-    pub bump: u8,
+impl CollectionMatch {
+    /// Pair `config` with `mint`'s metadata, requiring the NFT to be a verified member of
+    /// the config's collection.
+    pub fn resolve(
+        config: CollectionConfig,
+        nft_metadata: &AccountInfo,
+        mint: &Pubkey,
+    ) -> Result<Self> {
+        let metadata = MetadataPrefix::load(nft_metadata, mint)?;
+        require!(
+            metadata.verified_collection() == Some(config.collection),
+            MarketplaceError::InvalidCollection
+        );
+        Ok(Self { config, metadata })
+    }
+
+    /// The override for listing `mint`, and the verified collection the listing records.
+    /// `config` must be the collection's config PDA whenever the NFT has one, so a
+    /// configured collection's policy cannot be skipped by leaving it out. Programmable
+    /// NFTs are refused.
+    pub fn for_listing(
+        config: Option<&AccountInfo>,
+        nft_metadata: &AccountInfo,
+        mint: &Pubkey,
+        instance_seed: &[u8],
+    ) -> Result<(Option<Pubkey>, Option<Self>)> {
+        let metadata = MetadataPrefix::load(nft_metadata, mint)?;
+        require!(
+            !metadata.is_programmable(),
+            MarketplaceError::ProgrammableNftUnsupported
+        );
+        let Some(collection) = metadata.verified_collection() else {
+            return Ok((None, None));
+        };
+        let config_info = config.ok_or(MarketplaceError::MissingCollectionConfig)?;
+        let config = CollectionConfig::load_at(config_info, instance_seed, &collection)?;
+        Ok((
+            Some(collection),
+            config.map(|config| Self { config, metadata }),
+        ))
+    }
+
+    /// The override for selling `listing`: the config of the collection it recorded, or
+    /// of the NFT's verified collection for listings that predate the record. `config`
+    /// must be that collection's config PDA, as for `for_listing`.
+    pub fn for_sale(
+        listing: &Listing,
+        config: Option<&AccountInfo>,
+        nft_metadata: &AccountInfo,
+        instance_seed: &[u8],
+    ) -> Result<Option<Self>> {
+        let metadata = MetadataPrefix::load(nft_metadata, &listing.nft_mint)?;
+        let Some(collection) = listing.collection.or(metadata.verified_collection()) else {
+            return Ok(None);
+        };
+        let config_info = config.ok_or(MarketplaceError::MissingCollectionConfig)?;
+        let config = CollectionConfig::load_at(config_info, instance_seed, &collection)?;
+        Ok(config.map(|config| Self { config, metadata }))
+    }
+
+    /// Number of creator accounts a sale must pass to pay royalties.
+    pub fn royalty_recipients(&self) -> usize {
+        match (&self.metadata.creators, self.config.enforce_royalties) {
+            (Some(creators), true) => creators.len(),
+            _ => 0,
+        }
+    }
+
+    /// Royalty owed to each creator on a sale at `price`, in metadata creator order.
+    /// Empty unless the collection enforces royalties.
+    pub fn royalties(&self, price: u64) -> Result<Vec<(Pubkey, u64)>> {
+        let creators = match (&self.metadata.creators, self.config.enforce_royalties) {
+            (Some(creators), true) => creators,
+            _ => return Ok(Vec::new()),
+        };
+        let royalty = (price as u128)
+            .checked_mul(self.metadata.seller_fee_basis_points as u128)
+            .ok_or(MarketplaceError::Overflow)?
+            / 10_000;
+        creators
+            .iter()
+            .map(|creator| {
+                let amount = royalty * creator.share as u128 / 100;
+                let amount = u64::try_from(amount).map_err(|_| MarketplaceError::Overflow)?;
+                Ok((creator.address, amount))
+            })
+            .collect()
+    }
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct Listing {
-    pub seller: Pubkey,
-    pub nft_mint: Pubkey,
-    pub price: u64,
-    pub expiration_time: i64,
-    pub is_active: bool,
-    pub is_auction: bool,
-    pub highest_bid: u64,
-    pub highest_bidder: Pubkey,
-    pub created_at: i64,
-    pub bump: u8,
+// ─── Custody ─────────────────────────────────────────────────────────────────
+
+const FREEZE_DELEGATED_ACCOUNT: u8 = 26;
+const THAW_DELEGATED_ACCOUNT: u8 = 27;
+
+/// Accounts needed to move a listed NFT, whether it sits in the escrow token account
+/// or stays frozen in the seller's wallet with the escrow PDA as delegate.
+pub struct ListingCustody<'a, 'info> {
+    pub nft_mint: &'a AccountInfo<'info>,
+    pub escrow: &'a AccountInfo<'info>,
+    pub escrow_token_account: Option<&'a AccountInfo<'info>>,
+    pub seller_token_account: Option<&'a AccountInfo<'info>>,
+    pub nft_edition: Option<&'a AccountInfo<'info>>,
+    pub token_metadata_program: Option<&'a AccountInfo<'info>>,
+    pub token_program: &'a AccountInfo<'info>,
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct Offer {
-    pub offerer: Pubkey,
-    pub nft_mint: Pubkey,
-    pub amount: u64,
-    pub expiration_time: i64,
-    pub is_active: bool,
-    pub created_at: i64,
-    pub bump: u8,
+impl<'a, 'info> ListingCustody<'a, 'info> {
+    /// Transfer the NFT to `destination`, signed by the escrow PDA. Non-custodial
+    /// listings are thawed first and moved by the escrow as delegate.
+    pub fn release(
+        &self,
+        is_custodial: bool,
+        destination: &AccountInfo<'info>,
+        escrow_seeds: &[&[u8]],
+    ) -> Result<()> {
+        let source = if is_custodial {
+            self.escrow_token_account
+                .ok_or(MarketplaceError::MissingCustodyAccount)?
+        } else {
+            self.thaw(escrow_seeds)?;
+            self.seller_token_account
+                .ok_or(MarketplaceError::MissingCustodyAccount)?
+        };
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                self.token_program.clone(),
+                Transfer {
+                    from: source.clone(),
+                    to: destination.clone(),
+                    authority: self.escrow.clone(),
+                },
+                &[escrow_seeds],
+            ),
+            1,
+        )
+    }
+
+    /// Close the emptied escrow token account of a custodial listing, returning its rent
+    /// to `rent_recipient`. Non-custodial listings have no escrow token account.
+    pub fn close(
+        &self,
+        is_custodial: bool,
+        rent_recipient: &AccountInfo<'info>,
+        escrow_seeds: &[&[u8]],
+    ) -> Result<()> {
+        if !is_custodial {
+            return Ok(());
+        }
+        let escrow_token_account = self
+            .escrow_token_account
+            .ok_or(MarketplaceError::MissingCustodyAccount)?;
+
+        token::close_account(CpiContext::new_with_signer(
+            self.token_program.clone(),
+            CloseAccount {
+                account: escrow_token_account.clone(),
+                destination: rent_recipient.clone(),
+                authority: self.escrow.clone(),
+            },
+            &[escrow_seeds],
+        ))
+    }
+
+    /// Clear the escrow's delegation over a thawed in-place listing without the owner's
+    /// signature. The escrow moves the NFT into its own token account as delegate, which
+    /// spends the approval and unsets it, sends it back as owner, then closes its token
+    /// account to `rent_recipient`.
+    pub fn clear_delegate(
+        &self,
+        rent_recipient: &AccountInfo<'info>,
+        escrow_seeds: &[&[u8]],
+    ) -> Result<()> {
+        let seller_token_account = self
+            .seller_token_account
+            .ok_or(MarketplaceError::MissingCustodyAccount)?;
+        let escrow_token_account = self
+            .escrow_token_account
+            .ok_or(MarketplaceError::MissingCustodyAccount)?;
+
+        for (from, to) in [
+            (seller_token_account, escrow_token_account),
+            (escrow_token_account, seller_token_account),
+        ] {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    self.token_program.clone(),
+                    Transfer {
+                        from: from.clone(),
+                        to: to.clone(),
+                        authority: self.escrow.clone(),
+                    },
+                    &[escrow_seeds],
+                ),
+                1,
+            )?;
+        }
+        self.close(true, rent_recipient, escrow_seeds)
+    }
+
+    pub fn freeze(&self, escrow_seeds: &[&[u8]]) -> Result<()> {
+        self.invoke_delegated(FREEZE_DELEGATED_ACCOUNT, escrow_seeds)
+    }
+
+    pub fn thaw(&self, escrow_seeds: &[&[u8]]) -> Result<()> {
+        self.invoke_delegated(THAW_DELEGATED_ACCOUNT, escrow_seeds)
+    }
+
+    fn invoke_delegated(&self, discriminator: u8, escrow_seeds: &[&[u8]]) -> Result<()> {
+        let token_account = self
+            .seller_token_account
+            .ok_or(MarketplaceError::MissingCustodyAccount)?;
+        let edition = self
+            .nft_edition
+            .ok_or(MarketplaceError::MissingCustodyAccount)?;
+        let metadata_program = self
+            .token_metadata_program
+            .ok_or(MarketplaceError::MissingCustodyAccount)?;
+
+        let ix = Instruction {
+            program_id: TOKEN_METADATA_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(self.escrow.key(), true),
+                AccountMeta::new(token_account.key(), false),
+                AccountMeta::new_readonly(edition.key(), false),
+                AccountMeta::new_readonly(self.nft_mint.key(), false),
+                AccountMeta::new_readonly(self.token_program.key(), false),
+            ],
+            data: vec![discriminator],
+        };
+        invoke_signed(
+            &ix,
+            &[
+                self.escrow.clone(),
+                token_account.clone(),
+                edition.clone(),
+                self.nft_mint.clone(),
+                self.token_program.clone(),
+                metadata_program.clone(),
+            ],
+            &[escrow_seeds],
+        )?;
+        Ok(())
+    }
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct Escrow {
-    pub nft_mint: Pubkey,
-    pub bump: u8,
+// ─── Batches ─────────────────────────────────────────────────────────────────
+
+const LIST_BATCH_STRIDE: usize = 8;
+const CANCEL_BATCH_STRIDE: usize = 6;
+const BUY_BATCH_STRIDE: usize = 16;
+
+// Conservative per-item compute estimates; a batch stops before an item that might not fit
+const LIST_ITEM_COMPUTE_UNITS: u64 = 60_000;
+const CANCEL_ITEM_COMPUTE_UNITS: u64 = 40_000;
+const REPRICE_ITEM_COMPUTE_UNITS: u64 = 5_000;
+
+/// Split remaining accounts into `count` groups of `stride` accounts each.
+fn batch_chunks<'a, 'info>(
+    remaining: &'a [AccountInfo<'info>],
+    count: usize,
+    stride: usize,
+) -> Result<std::slice::Chunks<'a, AccountInfo<'info>>> {
+    require!(
+        count > 0 && remaining.len() == count * stride,
+        MarketplaceError::InvalidBatchAccounts
+    );
+    Ok(remaining.chunks(stride))
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct OfferEscrow {
-    pub nft_mint: Pubkey,
-    pub offerer: Pubkey,
-    pub bump: u8,
+/// Split the next `len` accounts off the front of `remaining`.
+fn take_accounts<'a, 'info>(
+    remaining: &'a [AccountInfo<'info>],
+    len: usize,
+) -> Result<(&'a [AccountInfo<'info>], &'a [AccountInfo<'info>])> {
+    require!(
+        remaining.len() >= len,
+        MarketplaceError::InvalidBatchAccounts
+    );
+    Ok(remaining.split_at(len))
 }
 
-// ─── Events ──────────────────────────────────────────────────────────────────
+fn has_compute_for(units: u64) -> bool {
+    anchor_lang::solana_program::compute_units::sol_remaining_compute_units() >= units
+}
 
-#[event]
-pub struct MarketplaceInitialized {
-    pub admin: Pubkey,
-    pub fee_recipient: Pubkey,
-    pub fee_bps: u16,
+/// Space `init_if_needed` expects of `account`: `space`, or its current size while it is a
+/// program account still in the legacy layout, which the handler then grows with
+/// `grow_legacy_account`. In the constraint, the field name is still the raw account.
+fn reopen_space(account: &AccountInfo, space: usize) -> usize {
+    if account.owner == &crate::ID {
+        account.data_len().min(space)
+    } else {
+        space
+    }
 }
 
-#[event]
-pub struct ListingCreated {
-    pub seller: Pubkey,
-    pub nft_mint: Pubkey,
-    pub price: u64,
-    pub is_auction: bool,
-    pub expiration_time: i64,
+/// Grow a program account still in the legacy layout to `space` bytes, `payer` topping
+/// up its rent, so it can be rewritten in the current layout.
+fn grow_legacy_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    space: usize,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    if account.data_len() >= space {
+        return Ok(());
+    }
+    let top_up = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(account.lamports());
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    account.realloc(space, true)?;
+    Ok(())
 }
 
-#[event]
-pub struct ListingCancelled {
-    pub nft_mint: Pubkey,
-    pub seller: Pubkey,
-}
+/// Create a program-owned PDA, tolerating accounts that were pre-funded with lamports.
+fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    space: usize,
+    seeds: &[&[u8]],
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let rent_lamports = Rent::get()?.minimum_balance(space);
+
+    if account.lamports() == 0 {
+        return anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                anchor_lang::system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+                &[seeds],
+            ),
+            rent_lamports,
+            space as u64,
+            &crate::ID,
+        );
+    }
 
-#[event]
-pub struct NftPurchased {
-    pub nft_mint: Pubkey,
-    pub buyer: Pubkey,
-    pub seller: Pubkey,
-    pub price: u64,
-    pub fee: u64,
+    let top_up = rent_lamports.saturating_sub(account.lamports());
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate {
+                account_to_allocate: account.clone(),
+            },
+            &[seeds],
+        ),
+        space as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Assign {
+                account_to_assign: account.clone(),
+            },
+            &[seeds],
+        ),
+        &crate::ID,
+    )
 }
 
-#[event]
-pub struct OfferCreated {
-    pub nft_mint: Pubkey,
-    pub offerer: Pubkey,
-    pub amount: u64,
-    pub expiration_time: i64,
+/// Load a program account, creating it at the PDA for `seeds` if it does not exist yet
+/// and growing it if it is still in the legacy layout. Returns the account and its bump.
+fn load_or_create_pda<'info, T>(
+    payer: &AccountInfo<'info>,
+    account: &'info AccountInfo<'info>,
+    seeds: &[&[u8]],
+    space: usize,
+    system_program: &AccountInfo<'info>,
+) -> Result<(Account<'info, T>, u8)>
+where
+    T: AccountSerialize + AccountDeserialize + Owner + Clone,
+{
+    let (expected, bump) = Pubkey::find_program_address(seeds, &crate::ID);
+    require_keys_eq!(
+        account.key(),
+        expected,
+        MarketplaceError::InvalidBatchAccounts
+    );
+
+    if account.owner == &crate::ID {
+        grow_legacy_account(account, payer, space, system_program)?;
+        return Ok((Account::try_from(account)?, bump));
+    }
+
+    let bump_seed = [bump];
+    let signer_seeds = [seeds, &[&bump_seed[..]]].concat();
+    create_pda_account(payer, account, space, &signer_seeds, system_program)?;
+    Ok((Account::try_from_unchecked(account)?, bump))
 }
 
-#[event]
-pub struct OfferCancelled {
-    pub nft_mint: Pubkey,
-    pub offerer: Pubkey,
-    pub amount: u64,
+fn list_batch_item<'info>(
+    accounts: &ListNftsBatch<'info>,
+    terms: &ListingTerms,
+    item_accounts: &'info [AccountInfo<'info>],
+    clock: &Clock,
+) -> Result<()> {
+    let [nft_mint, listing_info, escrow_info, seller_token_info, escrow_token_info, mint_blocklist, nft_metadata, collection_config] =
+        item_accounts
+    else {
+        return err!(MarketplaceError::InvalidBatchAccounts);
+    };
+    let instance_seed = accounts.marketplace.instance_seed();
+    let (verified_collection, collection) = CollectionMatch::for_listing(
+        Some(collection_config),
+        nft_metadata,
+        &nft_mint.key(),
+        instance_seed,
+    )?;
+    terms.validate(collection.as_ref().map(|collection| &collection.config))?;
+    BlocklistEntry::require_clear_at(mint_blocklist, instance_seed, &nft_mint.key())?;
+    let seller = accounts.seller.to_account_info();
+    let system_program = accounts.system_program.to_account_info();
+    let nft_mint_key = nft_mint.key();
+
+    let seller_token_account = Account::<TokenAccount>::try_from(seller_token_info)?;
+    require!(
+        seller_token_account.owner == seller.key()
+            && seller_token_account.mint == nft_mint_key
+            && seller_token_account.amount == 1,
+        MarketplaceError::SellerDoesNotOwnNft
+    );
+
+    let (mut listing, listing_bump) = load_or_create_pda::<Listing>(
+        &seller,
+        listing_info,
+        &[b"listing", instance_seed, nft_mint_key.as_ref()],
+        8 + Listing::INIT_SPACE,
+        &system_program,
+    )?;
+    // A listing PDA left open by an older sale can be reopened as long as it is inactive
+    require!(!listing.is_active, MarketplaceError::ListingNotActive);
+    listing.open(
+        seller.key(),
+        nft_mint_key,
+        terms,
+        true,
+        listing_bump,
+        clock.unix_timestamp,
+    )?;
+    listing.revision = accounts.marketplace.first_listing_revision()?;
+    listing.collection = verified_collection;
+    listing.exit(&crate::ID)?;
+
+    let (mut escrow, escrow_bump) = load_or_create_pda::<Escrow>(
+        &seller,
+        escrow_info,
+        &[b"escrow", instance_seed, nft_mint_key.as_ref()],
+        8 + Escrow::INIT_SPACE,
+        &system_program,
+    )?;
+    escrow.nft_mint = nft_mint_key;
+    escrow.bump = escrow_bump;
+    escrow.exit(&crate::ID)?;
+
+    // The associated token program verifies the escrow ATA address
+    anchor_spl::associated_token::create_idempotent(CpiContext::new(
+        accounts.associated_token_program.to_account_info(),
+        anchor_spl::associated_token::Create {
+            payer: seller.clone(),
+            associated_token: escrow_token_info.clone(),
+            authority: escrow_info.clone(),
+            mint: nft_mint.clone(),
+            system_program,
+            token_program: accounts.token_program.to_account_info(),
+        },
+    ))?;
+
+    token::transfer(
+        CpiContext::new(
+            accounts.token_program.to_account_info(),
+            Transfer {
+                from: seller_token_info.clone(),
+                to: escrow_token_info.clone(),
+                authority: seller,
+            },
+        ),
+        1,
+    )?;
+
+    emit!(ListingCreated {
+        seller: listing.seller,
+        nft_mint: listing.nft_mint,
+        price: listing.price,
+        is_auction: listing.is_auction,
+        is_custodial: true,
+        start_time: listing.start_time,
+        hold_period: listing.hold_period,
+        expiration_time: listing.expiration_time,
+    });
+
+    Ok(())
 }
 
-#[event]
-pub struct OfferAccepted {
-    pub nft_mint: Pubkey,
-    pub buyer: Pubkey,
-    pub seller: Pubkey,
-    pub price: u64,
-    pub fee: u64,
+fn cancel_batch_item<'info>(
+    accounts: &mut CancelListingsBatch<'info>,
+    item_accounts: &'info [AccountInfo<'info>],
+) -> Result<()> {
+    let [nft_mint, listing_info, escrow_info, escrow_token_info, seller_token_info, nft_edition] =
+        item_accounts
+    else {
+        return err!(MarketplaceError::InvalidBatchAccounts);
+    };
+    let nft_mint_key = nft_mint.key();
+
+    let instance_seed = accounts.marketplace.instance_seed();
+
+    let listing = Account::<Listing>::try_from(listing_info)?;
+    require!(
+        is_listing_pda(&listing.key(), instance_seed, &nft_mint_key, listing.bump),
+        MarketplaceError::InvalidBatchAccounts
+    );
+    require!(
+        accounts.seller.key() == listing.seller,
+        MarketplaceError::Unauthorized
+    );
+    require!(listing.is_active, MarketplaceError::ListingNotActive);
+
+    let escrow = Account::<Escrow>::try_from(escrow_info)?;
+    require!(
+        is_escrow_pda(&escrow.key(), instance_seed, &nft_mint_key, escrow.bump),
+        MarketplaceError::InvalidBatchAccounts
+    );
+
+    let seller_token_account = Account::<TokenAccount>::try_from(seller_token_info)?;
+    require!(
+        seller_token_account.owner == listing.seller && seller_token_account.mint == nft_mint_key,
+        MarketplaceError::InvalidSeller
+    );
+
+    let escrow_seeds: &[&[u8]] = &[
+        b"escrow",
+        instance_seed,
+        nft_mint_key.as_ref(),
+        &[escrow.bump],
+    ];
+    let custody = ListingCustody {
+        nft_mint,
+        escrow: escrow_info,
+        escrow_token_account: Some(escrow_token_info),
+        seller_token_account: Some(seller_token_info),
+        nft_edition: Some(nft_edition),
+        token_metadata_program: accounts.token_metadata_program.as_ref().map(|a| a.as_ref()),
+        token_program: accounts.token_program.as_ref(),
+    };
+
+    if listing.is_custodial {
+        custody.release(true, seller_token_info, escrow_seeds)?;
+        custody.close(true, accounts.seller.as_ref(), escrow_seeds)?;
+    } else {
+        custody.thaw(escrow_seeds)?;
+        token::revoke(CpiContext::new(
+            accounts.token_program.to_account_info(),
+            Revoke {
+                source: seller_token_info.clone(),
+                authority: accounts.seller.to_account_info(),
+            },
+        ))?;
+    }
+
+    emit!(ListingCancelled {
+        nft_mint: listing.nft_mint,
+        seller: listing.seller,
+    });
+    accounts.marketplace.listing_closed(&listing);
+
+    escrow.close(accounts.seller.to_account_info())?;
+    listing.close(accounts.seller.to_account_info())
 }
 
-#[event]
-pub struct PriceUpdated {
-    pub nft_mint: Pubkey,
-    pub old_price: u64,
-    pub new_price: u64,
+/// Whether `key` is the listing PDA of `nft_mint` in the instance with `instance_seed`.
+fn is_listing_pda(key: &Pubkey, instance_seed: &[u8], nft_mint: &Pubkey, bump: u8) -> bool {
+    Pubkey::create_program_address(
+        &[b"listing", instance_seed, nft_mint.as_ref(), &[bump]],
+        &crate::ID,
+    )
+    .is_ok_and(|expected| expected == *key)
 }
 
-#[event]
-pub struct MarketplacePausedEvent {
-    pub paused: bool,
+/// Whether `key` is the escrow PDA of `nft_mint` in the instance with `instance_seed`.
+fn is_escrow_pda(key: &Pubkey, instance_seed: &[u8], nft_mint: &Pubkey, bump: u8) -> bool {
+    Pubkey::create_program_address(
+        &[b"escrow", instance_seed, nft_mint.as_ref(), &[bump]],
+        &crate::ID,
+    )
+    .is_ok_and(|expected| expected == *key)
 }
 
-#[event]
-pub struct FeeUpdated {
-    pub old_fee_bps: u16,
-    pub new_fee_bps: u16,
+/// The item's listing if it is an active, unexpired fixed-price listing for its mint
+/// that `buyer` may buy.
+fn live_listing<'info>(
+    item_accounts: &'info [AccountInfo<'info>],
+    instance_seed: &[u8],
+    buyer: &Pubkey,
+    now: i64,
+) -> Option<Account<'info, Listing>> {
+    let (nft_mint, listing_info) = (item_accounts.get(1)?, item_accounts.get(2)?);
+    let listing = Account::<Listing>::try_from(listing_info).ok()?;
+    (is_listing_pda(&listing.key(), instance_seed, &nft_mint.key(), listing.bump)
+        && listing.is_active
+        && !listing.is_auction
+        && listing.has_started(now)
+        && listing.hold_period == 0
+        && listing.agent.is_none()
+        && listing.is_open_to(buyer)
+        && now < listing.expiration_time)
+        .then_some(listing)
 }
 
-#[event]
-pub struct FeeRecipientUpdated {
-    pub old_recipient: Pubkey,
-    pub new_recipient: Pubkey,
+fn buy_batch_item<'info>(
+    accounts: &mut BuyMany<'info>,
+    listing: Account<'info, Listing>,
+    item_accounts: &'info [AccountInfo<'info>],
+    collection: Option<&CollectionMatch>,
+    creators: &'info [AccountInfo<'info>],
+) -> Result<()> {
+    let [seller, nft_mint, _listing, escrow_info, escrow_token_info, seller_token_info, nft_edition, buyer_token_info, seller_stats_info, _collection_config, _nft_metadata, mint_blocklist, seller_blocklist, revenue_split, platform_mint_blocklist, platform_seller_blocklist] =
+        item_accounts
+    else {
+        return err!(MarketplaceError::InvalidBatchAccounts);
+    };
+    let nft_mint_key = nft_mint.key();
+    let buyer = accounts.buyer.to_account_info();
+
+    require_keys_eq!(
+        seller.key(),
+        listing.seller,
+        MarketplaceError::InvalidSeller
+    );
+    require!(
+        buyer.key() != listing.seller,
+        MarketplaceError::CannotBuyOwnListing
+    );
+    require!(!listing.is_auction, MarketplaceError::UseAuctionBidding);
+    let instance_seed = accounts.marketplace.instance_seed();
+    BlocklistEntry::require_clear_at(mint_blocklist, instance_seed, &nft_mint_key)?;
+    BlocklistEntry::require_clear_at(seller_blocklist, instance_seed, &listing.seller)?;
+    BlocklistEntry::require_clear_at(platform_mint_blocklist, &[], &nft_mint_key)?;
+    BlocklistEntry::require_clear_at(platform_seller_blocklist, &[], &listing.seller)?;
+    RevenueSplit::require_settled_at(revenue_split, &nft_mint_key, &listing.seller)?;
+
+    let escrow = Account::<Escrow>::try_from(escrow_info)?;
+    require!(
+        is_escrow_pda(&escrow.key(), instance_seed, &nft_mint_key, escrow.bump),
+        MarketplaceError::InvalidBatchAccounts
+    );
+
+    let price = listing.price;
+    let payout = SaleFees {
+        marketplace: &accounts.marketplace,
+        fee_tiers: FeeTierTable::load(&accounts.fee_tiers)?,
+        seller_stats: SellerStats::load_at(seller_stats_info, instance_seed, &listing.seller)?,
+        referrer: accounts.referrer.as_deref(),
+    }
+    .payout(price, collection)?;
+    let fee = payout.total_fee();
+
+    SalePayees {
+        seller,
+        fee_recipient: &accounts.fee_recipient,
+        referrer: accounts.referrer.as_mut(),
+        referrer_wallet: accounts.referrer_wallet.as_deref(),
+        referrer_blocklist: accounts.referrer_blocklist.as_deref(),
+        instance_seed,
+        creators,
+        split_recipients: &[],
+        agent_wallet: None,
+        system_program: accounts.system_program.as_ref(),
+    }
+    .pay(&payout, &buyer, nft_mint_key)?;
+    SellerStats::record_sale(seller_stats_info, payout.marketplace_fee)?;
+
+    // The associated token program verifies the buyer ATA address
+    anchor_spl::associated_token::create_idempotent(CpiContext::new(
+        accounts.associated_token_program.to_account_info(),
+        anchor_spl::associated_token::Create {
+            payer: buyer.clone(),
+            associated_token: buyer_token_info.clone(),
+            authority: buyer.clone(),
+            mint: nft_mint.clone(),
+            system_program: accounts.system_program.to_account_info(),
+            token_program: accounts.token_program.to_account_info(),
+        },
+    ))?;
+
+    let escrow_seeds: &[&[u8]] = &[
+        b"escrow",
+        instance_seed,
+        nft_mint_key.as_ref(),
+        &[escrow.bump],
+    ];
+    let custody = ListingCustody {
+        nft_mint,
+        escrow: escrow_info,
+        escrow_token_account: Some(escrow_token_info),
+        seller_token_account: Some(seller_token_info),
+        nft_edition: Some(nft_edition),
+        token_metadata_program: accounts.token_metadata_program.as_ref().map(|a| a.as_ref()),
+        token_program: accounts.token_program.as_ref(),
+    };
+    custody.release(listing.is_custodial, buyer_token_info, escrow_seeds)?;
+    custody.close(listing.is_custodial, seller, escrow_seeds)?;
+
+    emit!(NftPurchased {
+        nft_mint: nft_mint_key,
+        buyer: buyer.key(),
+        seller: listing.seller,
+        price,
+        fee,
+    });
+
+    escrow.close(seller.clone())?;
+    listing.close(seller.clone())
 }
 
 // ─── Errors ──────────────────────────────────────────────────────────────────
@@ -1039,7 +7923,7 @@ pub enum MarketplaceError {
     NotPaused,
     #[msg("Price must be greater than 0")]
     PriceMustBePositive,
-    #[msg("Listing duration too short (minimum 24 hours)")]
+    #[msg("Listing duration is below the minimum")]
     DurationTooShort,
     #[msg("Seller does not own the NFT")]
     SellerDoesNotOwnNft,
@@ -1075,4 +7959,1112 @@ pub enum MarketplaceError {
     Overflow,
     #[msg("Nothing to withdraw")]
     NothingToWithdraw,
+    #[msg("Loan principal must be greater than 0")]
+    PrincipalMustBePositive,
+    #[msg("APR too high (maximum 500%)")]
+    AprTooHigh,
+    #[msg("Loan duration must be between 1 day and 1 year")]
+    InvalidLoanDuration,
+    #[msg("Cannot borrow against your own loan offer")]
+    CannotBorrowFromSelf,
+    #[msg("NFT is not part of the expected verified collection")]
+    InvalidCollection,
+    #[msg("Invalid NFT metadata account")]
+    InvalidMetadata,
+    #[msg("Invalid lender account")]
+    InvalidLender,
+    #[msg("Invalid borrower account")]
+    InvalidBorrower,
+    #[msg("Loan has not defaulted yet")]
+    LoanNotDefaulted,
+    #[msg("Token account is already frozen")]
+    TokenAccountFrozen,
+    #[msg("Mint freeze authority is not the provided edition")]
+    InvalidFreezeAuthority,
+    #[msg("Missing account required to move the listed NFT")]
+    MissingCustodyAccount,
+    #[msg("Batch accounts do not match the requested items")]
+    InvalidBatchAccounts,
+    #[msg("Listing is missing, sold or expired")]
+    ListingUnavailable,
+    #[msg("Total price exceeds the buyer's maximum")]
+    PriceCapExceeded,
+    #[msg("Listing terms changed since the transaction was built")]
+    ListingChanged,
+    #[msg("Too many fee tiers (maximum 8)")]
+    TooManyFeeTiers,
+    #[msg("Fee tiers must be sorted by strictly increasing fees paid")]
+    FeeTiersNotSorted,
+    #[msg("Treasury recipients must be 1 to 5 non-default wallets totalling 10000 bps")]
+    InvalidTreasuryRecipients,
+    #[msg("Referral share too high (maximum 50% of the fee)")]
+    ReferralShareTooHigh,
+    #[msg("Referrer wallet does not match the referrer account")]
+    InvalidReferrer,
+    #[msg("Collection does not allow this settlement currency")]
+    CurrencyNotAllowed,
+    #[msg("Too many allowed currencies (maximum 4)")]
+    TooManyCurrencies,
+    #[msg("Creator accounts do not match the NFT metadata")]
+    InvalidCreator,
+    #[msg("Queued change cannot be executed before its timelock elapses")]
+    TimelockNotElapsed,
+    #[msg("Role holder cannot be the default pubkey")]
+    InvalidRoleHolder,
+    #[msg("New admin must be a different, non-default pubkey")]
+    InvalidAdmin,
+    #[msg("Signer is not the proposed admin")]
+    NotPendingAdmin,
+    #[msg("Pause flags must be a non-empty mask of known operations")]
+    InvalidPauseFlags,
+    #[msg("Mint or wallet is blocklisted")]
+    Blocklisted,
+    #[msg("Instance ID cannot be the default pubkey")]
+    InvalidInstance,
+    #[msg("Account uses a legacy layout and must be migrated first")]
+    AccountNotMigrated,
+    #[msg("Marketplace is winding down")]
+    MarketplaceWindingDown,
+    #[msg("Marketplace is not winding down")]
+    NotWindingDown,
+    #[msg("Listings, offers or bidding pools still hold escrowed assets")]
+    EscrowOutstanding,
+    #[msg("Bidding pool balance does not cover the amount")]
+    InsufficientPoolBalance,
+    #[msg("Offer escrow or bidding pool account missing")]
+    MissingOfferFunding,
+    #[msg("An escrow-funded offer must be cancelled before making a pool-backed one")]
+    OfferFundingMismatch,
+    #[msg("Auto-accept price must be positive, at most the price, and not on an auction")]
+    InvalidAutoAcceptPrice,
+    #[msg("Offer meets the auto-accept price but sale accounts are missing")]
+    MissingSettlementAccount,
+    #[msg("Start time must be in the future")]
+    InvalidStartTime,
+    #[msg("Listing has not started yet")]
+    ListingNotStarted,
+    #[msg("Expiration can only be extended into the future")]
+    InvalidExpiration,
+    #[msg("Cannot change the sale type of an auction with bids")]
+    AuctionHasBids,
+    #[msg("Hold period must be between zero and thirty days")]
+    InvalidHoldPeriod,
+    #[msg("A settlement account must be passed exactly when the listing has a hold period")]
+    SettlementMismatch,
+    #[msg("Settlement is under dispute")]
+    SettlementDisputed,
+    #[msg("Settlement is not under dispute")]
+    SettlementNotDisputed,
+    #[msg("Dispute window has closed")]
+    DisputeWindowClosed,
+    #[msg("Settlement is still within its dispute window")]
+    SettlementLocked,
+    #[msg("Refund exceeds the held proceeds")]
+    InvalidRefundAmount,
+    #[msg("Consignment agent is missing or does not match the listing")]
+    InvalidAgent,
+    #[msg("Agent commission exceeds the allowed share of the sale")]
+    InvalidCommission,
+    #[msg("Revenue split recipients must be 1-8 non-default wallets whose shares sum to 100%")]
+    InvalidRevenueSplit,
+    #[msg("Revenue splits must be set before the NFT is minted")]
+    MintAlreadyIssued,
+    #[msg("First sale owes a revenue split; buy through buy_nft or accept_offer")]
+    RevenueSplitPending,
+    #[msg("The collection config account of the NFT's collection must be passed")]
+    MissingCollectionConfig,
+    #[msg("The account this admin change applies to must be passed")]
+    MissingChangeAccount,
+    #[msg("Programmable NFTs are not supported")]
+    ProgrammableNftUnsupported,
+    #[msg("Listing is reserved for another buyer")]
+    ReservedForAnotherBuyer,
+    #[msg("The seller cannot be the reserved buyer")]
+    InvalidReservedBuyer,
+    #[msg("Marketplace must be migrated with migrate_marketplace first")]
+    MarketplaceNotMigrated,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86_400;
+    const NOW: i64 = 1_700_000_000;
+
+    fn marketplace() -> Marketplace {
+        let mut marketplace = Marketplace::from_legacy(legacy::Marketplace {
+            admin: Pubkey::new_unique(),
+            fee_recipient: Pubkey::new_unique(),
+            fee_bps: 250,
+            paused: false,
+            listing_count: 0,
+            bump: 255,
+        });
+        marketplace.version = Marketplace::VERSION;
+        marketplace
+    }
+
+    fn terms(price: u64) -> ListingTerms {
+        ListingTerms {
+            price,
+            duration: 7 * DAY,
+            is_auction: false,
+            auto_accept_price: None,
+            start_time: None,
+            hold_period: 0,
+        }
+    }
+
+    fn legacy_listing() -> legacy::Listing {
+        legacy::Listing {
+            seller: Pubkey::new_unique(),
+            nft_mint: Pubkey::new_unique(),
+            price: 1_000,
+            expiration_time: NOW + DAY,
+            is_active: true,
+            is_auction: false,
+            highest_bid: 0,
+            highest_bidder: Pubkey::default(),
+            created_at: NOW,
+            bump: 254,
+        }
+    }
+
+    /// Serialized data of a program account, discriminator first.
+    fn account_data<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn account_info<'a>(
+        key: &'a Pubkey,
+        owner: &'a Pubkey,
+        lamports: &'a mut u64,
+        data: &'a mut [u8],
+    ) -> AccountInfo<'a> {
+        AccountInfo::new(key, false, false, lamports, data, owner, false, 0)
+    }
+
+    /// A listing opened on `marketplace` the way the listing instructions open one.
+    fn open_listing(marketplace: &mut Marketplace, terms: &ListingTerms) -> Listing {
+        let mut listing = Listing::from_legacy(legacy_listing());
+        listing
+            .open(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                terms,
+                true,
+                254,
+                NOW,
+            )
+            .unwrap();
+        listing.revision = marketplace.first_listing_revision().unwrap();
+        marketplace.listings_opened(1).unwrap();
+        listing
+    }
+
+    fn loan(principal: u64, apr_bps: u16, duration: i64) -> Loan {
+        Loan {
+            borrower: Pubkey::new_unique(),
+            lender: Pubkey::new_unique(),
+            nft_mint: Pubkey::new_unique(),
+            collection: Pubkey::new_unique(),
+            principal,
+            apr_bps,
+            start_time: 1_000,
+            due_time: 1_000 + duration,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn loan_interest_accrues_linearly_and_stops_at_the_due_time() {
+        let loan = loan(365_000_000, 1_000, 365 * DAY);
+
+        assert_eq!(loan.interest_due(loan.start_time).unwrap(), 0);
+        assert_eq!(loan.interest_due(loan.start_time + DAY).unwrap(), 100_000);
+        assert_eq!(loan.interest_due(loan.due_time).unwrap(), 36_500_000);
+        assert_eq!(
+            loan.interest_due(loan.due_time + 30 * DAY).unwrap(),
+            36_500_000
+        );
+    }
+
+    #[test]
+    fn loan_interest_is_zero_before_the_start_and_at_zero_apr() {
+        let early = loan(1_000_000_000, 2_500, 30 * DAY);
+        assert_eq!(early.interest_due(early.start_time - DAY).unwrap(), 0);
+
+        let free = loan(1_000_000_000, 0, 30 * DAY);
+        assert_eq!(free.interest_due(free.due_time).unwrap(), 0);
+    }
+
+    #[test]
+    fn loan_interest_handles_the_largest_principal() {
+        let loan = loan(u64::MAX, 10_000, 365 * DAY);
+        assert_eq!(loan.interest_due(loan.due_time).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn batch_items_must_be_their_mints_pdas_on_the_instance() {
+        let mint = Pubkey::new_unique();
+        let instance = Pubkey::new_unique();
+        let (listing, listing_bump) =
+            Pubkey::find_program_address(&[b"listing", mint.as_ref()], &crate::ID);
+        let (escrow, escrow_bump) = Pubkey::find_program_address(
+            &[b"escrow", instance.as_ref(), mint.as_ref()],
+            &crate::ID,
+        );
+
+        assert!(is_listing_pda(&listing, &[], &mint, listing_bump));
+        assert!(!is_listing_pda(
+            &listing,
+            instance.as_ref(),
+            &mint,
+            listing_bump
+        ));
+        assert!(is_escrow_pda(
+            &escrow,
+            instance.as_ref(),
+            &mint,
+            escrow_bump
+        ));
+        assert!(!is_escrow_pda(&escrow, &[], &mint, escrow_bump));
+        assert!(!is_escrow_pda(
+            &escrow,
+            instance.as_ref(),
+            &Pubkey::new_unique(),
+            escrow_bump
+        ));
+        assert!(!is_escrow_pda(&listing, &[], &mint, listing_bump));
+    }
+
+    #[test]
+    fn a_relisted_mint_starts_above_every_revision_of_its_last_listing() {
+        let mut marketplace = marketplace();
+        let mut first = open_listing(&mut marketplace, &terms(1_000));
+        for price in 2_000..2_010 {
+            first.set_price(price).unwrap();
+        }
+        marketplace.listing_closed(&first);
+
+        let relisted = open_listing(&mut marketplace, &terms(1_000));
+        assert!(relisted.revision > first.revision);
+        assert_eq!(marketplace.listing_count, 2);
+    }
+
+    fn fee_tiers() -> FeeTierTable {
+        FeeTierTable {
+            tiers: vec![
+                FeeTier {
+                    min_fees_paid: 1_000_000,
+                    fee_bps: 200,
+                },
+                FeeTier {
+                    min_fees_paid: 10_000_000,
+                    fee_bps: 100,
+                },
+            ],
+            bump: 255,
+        }
+    }
+
+    fn seller_stats(fees_paid: u64) -> SellerStats {
+        SellerStats {
+            seller: Pubkey::new_unique(),
+            sales: 1,
+            fees_paid,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn a_seller_gets_the_highest_fee_tier_their_recorded_fees_reach() {
+        let table = fee_tiers();
+        assert_eq!(table.fee_bps_for(999_999), None);
+        assert_eq!(table.fee_bps_for(1_000_000), Some(200));
+        assert_eq!(table.fee_bps_for(9_999_999), Some(200));
+        assert_eq!(table.fee_bps_for(u64::MAX), Some(100));
+
+        let stats = seller_stats(10_000_000);
+        assert_eq!(seller_fee_bps(250, Some(&table), Some(&stats)), 100);
+        assert_eq!(
+            seller_fee_bps(250, Some(&table), Some(&seller_stats(0))),
+            250
+        );
+        assert_eq!(seller_fee_bps(250, None, Some(&stats)), 250);
+        assert_eq!(seller_fee_bps(250, Some(&table), None), 250);
+    }
+
+    #[test]
+    fn the_fee_tier_table_is_read_only_once_the_program_owns_it() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut empty = [];
+        let missing = account_info(
+            &key,
+            &anchor_lang::system_program::ID,
+            &mut lamports,
+            &mut empty,
+        );
+        assert!(FeeTierTable::load(&missing).unwrap().is_none());
+
+        let mut lamports = 0;
+        let mut data = account_data(&fee_tiers());
+        let spoofed_owner = Pubkey::new_unique();
+        let spoofed = account_info(&key, &spoofed_owner, &mut lamports, &mut data);
+        assert!(FeeTierTable::load(&spoofed).unwrap().is_none());
+
+        let mut lamports = 0;
+        let mut data = account_data(&fee_tiers());
+        let table = account_info(&key, &crate::ID, &mut lamports, &mut data);
+        let loaded = FeeTierTable::load(&table).unwrap().unwrap();
+        assert_eq!(loaded.tiers.len(), 2);
+        assert_eq!(loaded.fee_bps_for(10_000_000), Some(100));
+    }
+
+    #[test]
+    fn fee_tier_changes_must_be_sorted_capped_and_few() {
+        let valid = AdminChange::FeeTiers {
+            tiers: fee_tiers().tiers,
+        };
+        assert!(valid.validate().is_ok());
+
+        let mut unsorted = fee_tiers().tiers;
+        unsorted.reverse();
+        assert_eq!(
+            AdminChange::FeeTiers { tiers: unsorted }
+                .validate()
+                .unwrap_err(),
+            MarketplaceError::FeeTiersNotSorted.into()
+        );
+
+        let mut too_high = fee_tiers().tiers;
+        too_high[0].fee_bps = 1_001;
+        assert_eq!(
+            AdminChange::FeeTiers { tiers: too_high }
+                .validate()
+                .unwrap_err(),
+            MarketplaceError::FeeTooHigh.into()
+        );
+
+        let too_many = (0..=FeeTierTable::MAX_TIERS as u64)
+            .map(|min_fees_paid| FeeTier {
+                min_fees_paid,
+                fee_bps: 100,
+            })
+            .collect();
+        assert_eq!(
+            AdminChange::FeeTiers { tiers: too_many }
+                .validate()
+                .unwrap_err(),
+            MarketplaceError::TooManyFeeTiers.into()
+        );
+    }
+
+    fn fee_share(bps: u16) -> FeeShare {
+        FeeShare {
+            recipient: Pubkey::new_unique(),
+            bps,
+        }
+    }
+
+    #[test]
+    fn fee_shares_round_down_and_survive_the_largest_amount() {
+        assert_eq!(fee_share(2_500).portion_of(1_001).unwrap(), 250);
+        assert_eq!(fee_share(10_000).portion_of(u64::MAX).unwrap(), u64::MAX);
+        assert_eq!(fee_share(0).portion_of(u64::MAX).unwrap(), 0);
+    }
+
+    #[test]
+    fn treasury_recipients_must_be_set_and_total_the_whole_fee() {
+        assert!(Treasury::validate_recipients(&[fee_share(6_000), fee_share(4_000)]).is_ok());
+
+        let invalid: [Vec<FeeShare>; 4] = [
+            vec![],
+            vec![fee_share(6_000), fee_share(3_999)],
+            vec![
+                fee_share(6_000),
+                FeeShare {
+                    recipient: Pubkey::default(),
+                    bps: 4_000,
+                },
+            ],
+            // One recipient too many, though their shares do total 10000 bps
+            (0..=Treasury::MAX_RECIPIENTS)
+                .map(|i| fee_share(if i == 0 { 5_000 } else { 1_000 }))
+                .collect(),
+        ];
+        for recipients in invalid {
+            assert_eq!(
+                Treasury::validate_recipients(&recipients).unwrap_err(),
+                MarketplaceError::InvalidTreasuryRecipients.into()
+            );
+        }
+    }
+
+    fn referrer(share_bps: u16) -> Referrer {
+        Referrer {
+            wallet: Pubkey::new_unique(),
+            share_bps,
+            total_earned: 0,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn a_referrer_is_paid_out_of_the_marketplace_fee() {
+        let royalty = (Pubkey::new_unique(), 50_000);
+        let payout =
+            SalePayout::new(1_000_000, 250, Some(&referrer(2_000)), vec![royalty]).unwrap();
+
+        assert_eq!(payout.total_fee(), 25_000);
+        assert_eq!(payout.referral_fee, 5_000);
+        assert_eq!(payout.marketplace_fee, 20_000);
+        assert_eq!(payout.seller_amount, 1_000_000 - 25_000 - 50_000);
+
+        let direct = SalePayout::new(1_000_000, 250, None, Vec::new()).unwrap();
+        assert_eq!(direct.referral_fee, 0);
+        assert_eq!(direct.marketplace_fee, 25_000);
+        assert_eq!(direct.seller_amount, 975_000);
+    }
+
+    #[test]
+    fn sale_fees_apply_the_seller_tier_before_the_referral_split() {
+        let marketplace = marketplace();
+        let referrer = referrer(5_000);
+        let fees = SaleFees {
+            marketplace: &marketplace,
+            fee_tiers: Some(fee_tiers()),
+            seller_stats: Some(seller_stats(1_000_000)),
+            referrer: Some(&referrer),
+        };
+        let payout = fees.payout(1_000_000, None).unwrap();
+        assert_eq!(payout.total_fee(), 20_000);
+        assert_eq!(payout.referral_fee, 10_000);
+        assert_eq!(payout.seller_amount, 980_000);
+    }
+
+    #[test]
+    fn fees_and_royalties_cannot_exceed_the_price() {
+        assert_eq!(marketplace_fee(1_000_001, 10_000).unwrap(), 1_000_001);
+        assert_eq!(
+            marketplace_fee(u64::MAX, 2).unwrap_err(),
+            MarketplaceError::Overflow.into()
+        );
+        let royalties = vec![(Pubkey::new_unique(), 990_000)];
+        assert_eq!(
+            SalePayout::new(1_000_000, 250, None, royalties)
+                .err()
+                .unwrap(),
+            MarketplaceError::Overflow.into()
+        );
+    }
+
+    #[test]
+    fn pausing_one_operation_leaves_the_others_open() {
+        let mut marketplace = marketplace();
+        marketplace.paused = Marketplace::PAUSE_BUYING;
+
+        assert_eq!(
+            marketplace
+                .require_unpaused(Marketplace::PAUSE_BUYING)
+                .unwrap_err(),
+            MarketplaceError::MarketplacePaused.into()
+        );
+        assert!(marketplace
+            .require_unpaused(Marketplace::PAUSE_LISTING)
+            .is_ok());
+        assert!(marketplace
+            .require_unpaused(Marketplace::PAUSE_LISTING | Marketplace::PAUSE_BUYING)
+            .is_err());
+
+        assert!(Marketplace::validate_pause_flags(Marketplace::PAUSE_ALL).is_ok());
+        assert!(Marketplace::validate_pause_flags(0).is_err());
+        assert!(Marketplace::validate_pause_flags(1 << 7).is_err());
+    }
+
+    #[test]
+    fn a_wind_down_blocks_everything_but_withdrawals() {
+        let mut marketplace = marketplace();
+        marketplace.winding_down = true;
+
+        assert!(marketplace
+            .require_unpaused(Marketplace::PAUSE_WITHDRAWALS)
+            .is_ok());
+        assert_eq!(
+            marketplace
+                .require_unpaused(Marketplace::PAUSE_LENDING)
+                .unwrap_err(),
+            MarketplaceError::MarketplaceWindingDown.into()
+        );
+    }
+
+    #[test]
+    fn a_flagged_key_is_refused_and_an_unflagged_one_passes() {
+        let flagged_key = Pubkey::new_unique();
+        let (entry_key, bump) =
+            Pubkey::find_program_address(&[b"blocklist", flagged_key.as_ref()], &crate::ID);
+
+        let mut lamports = 0;
+        let mut empty = [];
+        let system_program = anchor_lang::system_program::ID;
+        let unflagged = account_info(&entry_key, &system_program, &mut lamports, &mut empty);
+        assert!(BlocklistEntry::require_clear(&unflagged, &flagged_key).is_ok());
+        assert!(BlocklistEntry::require_clear_at(&unflagged, &[], &flagged_key).is_ok());
+
+        let mut lamports = 0;
+        let mut data = account_data(&BlocklistEntry {
+            key: flagged_key,
+            reason: 1,
+            flagged_by: Pubkey::new_unique(),
+            flagged_at: NOW,
+            bump,
+        });
+        let entry = account_info(&entry_key, &crate::ID, &mut lamports, &mut data);
+        assert_eq!(
+            BlocklistEntry::require_clear(&entry, &flagged_key).unwrap_err(),
+            MarketplaceError::Blocklisted.into()
+        );
+        assert_eq!(
+            BlocklistEntry::require_clear_at(&entry, &[], &flagged_key).unwrap_err(),
+            MarketplaceError::Blocklisted.into()
+        );
+    }
+
+    #[test]
+    fn a_blocklist_entry_for_another_key_or_instance_is_rejected() {
+        let key = Pubkey::new_unique();
+        let (entry_key, _) =
+            Pubkey::find_program_address(&[b"blocklist", key.as_ref()], &crate::ID);
+        let mut lamports = 0;
+        let mut empty = [];
+        let system_program = anchor_lang::system_program::ID;
+        let entry = account_info(&entry_key, &system_program, &mut lamports, &mut empty);
+
+        let instance = Pubkey::new_unique();
+        assert_eq!(
+            BlocklistEntry::require_clear_at(&entry, instance.as_ref(), &key).unwrap_err(),
+            MarketplaceError::InvalidBatchAccounts.into()
+        );
+        assert_eq!(
+            BlocklistEntry::require_clear_at(&entry, &[], &Pubkey::new_unique()).unwrap_err(),
+            MarketplaceError::InvalidBatchAccounts.into()
+        );
+    }
+
+    fn legacy_marketplace() -> legacy::Marketplace {
+        legacy::Marketplace {
+            admin: Pubkey::new_unique(),
+            fee_recipient: Pubkey::new_unique(),
+            fee_bps: 250,
+            paused: true,
+            listing_count: 42,
+            bump: 253,
+        }
+    }
+
+    #[test]
+    fn a_legacy_marketplace_reads_with_defaults_and_writes_back_unchanged() {
+        let legacy = legacy_marketplace();
+        let legacy_data = account_data(&legacy);
+
+        let marketplace = Marketplace::try_deserialize(&mut &legacy_data[..]).unwrap();
+        assert_eq!(marketplace.version, 0);
+        assert_eq!(marketplace.paused, Marketplace::PAUSE_ALL);
+        assert_eq!(marketplace.listing_count, 42);
+        assert_eq!(marketplace.pauser, legacy.admin);
+        assert_eq!(marketplace.treasurer, legacy.admin);
+        assert_eq!(marketplace.instance_seed(), &[] as &[u8]);
+
+        assert_eq!(account_data(&marketplace), legacy_data);
+    }
+
+    #[test]
+    fn a_legacy_account_cannot_be_written_with_fields_it_lacks() {
+        let data = account_data(&legacy_marketplace());
+        let mut marketplace = Marketplace::try_deserialize(&mut &data[..]).unwrap();
+        marketplace.open_offers = 1;
+
+        let mut written = Vec::new();
+        assert_eq!(
+            marketplace.try_serialize(&mut written).unwrap_err(),
+            MarketplaceError::AccountNotMigrated.into()
+        );
+    }
+
+    #[test]
+    fn a_grown_legacy_account_reads_as_legacy_until_its_version_is_set() {
+        let mut data = account_data(&legacy_marketplace());
+        data.resize(8 + Marketplace::INIT_SPACE, 0);
+
+        let mut marketplace = Marketplace::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(marketplace.version, 0);
+
+        marketplace.version = Marketplace::VERSION;
+        marketplace.open_offers = 3;
+        let migrated = account_data(&marketplace);
+        assert!(migrated.len() <= data.len());
+        assert_eq!(
+            migrated[8 + legacy::Marketplace::INIT_SPACE],
+            Marketplace::VERSION
+        );
+        let reread = Marketplace::try_deserialize(&mut &migrated[..]).unwrap();
+        assert!(reread == marketplace);
+    }
+
+    #[test]
+    fn listings_and_offers_round_trip_in_both_layouts() {
+        let legacy = legacy_listing();
+        let legacy_data = account_data(&legacy);
+        let listing = Listing::try_deserialize(&mut &legacy_data[..]).unwrap();
+        assert!(listing.is_custodial);
+        assert!(!listing.counted);
+        assert_eq!(account_data(&listing), legacy_data);
+
+        let mut marketplace = marketplace();
+        let mut current = open_listing(&mut marketplace, &terms(5_000));
+        current.reserved_buyer = Pubkey::new_unique();
+        let data = account_data(&current);
+        assert!(data.len() <= 8 + Listing::INIT_SPACE);
+        assert!(Listing::try_deserialize(&mut &data[..]).unwrap() == current);
+
+        let legacy_offer = legacy::Offer {
+            offerer: Pubkey::new_unique(),
+            nft_mint: Pubkey::new_unique(),
+            amount: 1_000,
+            expiration_time: NOW + DAY,
+            is_active: true,
+            created_at: NOW,
+            bump: 252,
+        };
+        let offer_data = account_data(&legacy_offer);
+        let offer = Offer::try_deserialize(&mut &offer_data[..]).unwrap();
+        assert_eq!(offer.version, 0);
+        assert!(!offer.counted);
+        assert_eq!(account_data(&offer), offer_data);
+    }
+
+    #[test]
+    fn a_legacy_marketplace_opens_nothing_until_migrated() {
+        let data = account_data(&legacy_marketplace());
+        let mut marketplace = Marketplace::try_deserialize(&mut &data[..]).unwrap();
+
+        assert_eq!(
+            marketplace.listings_opened(1).unwrap_err(),
+            MarketplaceError::MarketplaceNotMigrated.into()
+        );
+        assert!(marketplace.offer_opened().is_err());
+        assert!(marketplace.loan_opened().is_err());
+
+        // Closing legacy listings, which were never counted, leaves it writable
+        marketplace.listing_closed(&Listing::from_legacy(legacy_listing()));
+        assert!(marketplace.to_legacy().is_some());
+    }
+
+    fn wind_down_report() -> WindDownReport {
+        WindDownReport {
+            started_at: NOW,
+            listings_outstanding: 0,
+            offers_outstanding: 0,
+            pools_outstanding: 0,
+            settlements_outstanding: 0,
+            loan_offers_outstanding: 0,
+            loans_outstanding: 0,
+            listings_returned: 0,
+            offers_refunded: 0,
+            pools_refunded: 0,
+            loan_offers_returned: 0,
+            lamports_refunded: 0,
+            completed_at: None,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn the_wind_down_report_mirrors_every_open_escrow() {
+        let mut marketplace = marketplace();
+        let listing = open_listing(&mut marketplace, &terms(1_000));
+        open_listing(&mut marketplace, &terms(2_000));
+        marketplace.offer_opened().unwrap();
+        marketplace.bidding_pool_opened().unwrap();
+        marketplace.bidding_pool_opened().unwrap();
+        marketplace.settlement_opened().unwrap();
+        marketplace.loan_offer_opened().unwrap();
+        marketplace.loan_opened().unwrap();
+
+        let mut report = wind_down_report();
+        report.sync(&marketplace);
+        assert_eq!(report.listings_outstanding, 2);
+        assert_eq!(report.offers_outstanding, 1);
+        assert_eq!(report.pools_outstanding, 2);
+        assert_eq!(report.settlements_outstanding, 1);
+        assert_eq!(report.loan_offers_outstanding, 1);
+        assert_eq!(report.loans_outstanding, 1);
+
+        marketplace.listing_closed(&listing);
+        marketplace.bidding_pools_closed(2);
+        marketplace.settlement_closed();
+        marketplace.loan_offer_closed();
+        marketplace.loan_closed();
+        report.sync(&marketplace);
+        assert_eq!(report.listings_outstanding, 1);
+        assert_eq!(report.offers_outstanding, 1);
+        assert_eq!(report.pools_outstanding, 0);
+        assert_eq!(report.settlements_outstanding, 0);
+        assert_eq!(report.loan_offers_outstanding, 0);
+        assert_eq!(report.loans_outstanding, 0);
+    }
+
+    #[test]
+    fn closing_an_uncounted_listing_or_offer_leaves_the_counts_alone() {
+        let mut marketplace = marketplace();
+        open_listing(&mut marketplace, &terms(1_000));
+        marketplace.offer_opened().unwrap();
+
+        marketplace.listing_closed(&Listing::from_legacy(legacy_listing()));
+        marketplace.offer_closed(&Offer::from_legacy(legacy::Offer {
+            offerer: Pubkey::new_unique(),
+            nft_mint: Pubkey::new_unique(),
+            amount: 1_000,
+            expiration_time: NOW + DAY,
+            is_active: true,
+            created_at: NOW,
+            bump: 252,
+        }));
+        assert_eq!(marketplace.active_listings, 1);
+        assert_eq!(marketplace.open_offers, 1);
+    }
+
+    fn no_update() -> ListingUpdate {
+        ListingUpdate {
+            price: None,
+            expiration_time: None,
+            is_auction: None,
+            reserved_buyer: None,
+        }
+    }
+
+    #[test]
+    fn a_reserved_listing_is_only_open_to_its_buyer() {
+        let mut listing = open_listing(&mut marketplace(), &terms(1_000));
+        let buyer = Pubkey::new_unique();
+        let stranger = Pubkey::new_unique();
+        assert!(listing.is_open_to(&stranger));
+
+        let revision = listing.revision;
+        listing
+            .apply(
+                &ListingUpdate {
+                    reserved_buyer: Some(buyer),
+                    ..no_update()
+                },
+                NOW,
+            )
+            .unwrap();
+        assert!(listing.revision > revision);
+        assert!(listing.is_open_to(&buyer));
+        assert!(listing.require_open_to(&buyer).is_ok());
+        assert_eq!(
+            listing.require_open_to(&stranger).unwrap_err(),
+            MarketplaceError::ReservedForAnotherBuyer.into()
+        );
+
+        listing
+            .apply(
+                &ListingUpdate {
+                    reserved_buyer: Some(Pubkey::default()),
+                    ..no_update()
+                },
+                NOW,
+            )
+            .unwrap();
+        assert!(listing.is_open_to(&stranger));
+    }
+
+    #[test]
+    fn the_seller_cannot_reserve_their_own_listing() {
+        let mut listing = open_listing(&mut marketplace(), &terms(1_000));
+        let update = ListingUpdate {
+            reserved_buyer: Some(listing.seller),
+            ..no_update()
+        };
+        assert_eq!(
+            listing.apply(&update, NOW).unwrap_err(),
+            MarketplaceError::InvalidReservedBuyer.into()
+        );
+    }
+
+    #[test]
+    fn relisting_clears_the_reserved_buyer() {
+        let mut marketplace = marketplace();
+        let mut listing = open_listing(&mut marketplace, &terms(1_000));
+        listing.reserved_buyer = Pubkey::new_unique();
+        listing
+            .open(
+                listing.seller,
+                listing.nft_mint,
+                &terms(2_000),
+                true,
+                listing.bump,
+                NOW,
+            )
+            .unwrap();
+        assert_eq!(listing.reserved_buyer, Pubkey::default());
+    }
+
+    #[test]
+    fn a_listing_with_bids_cannot_change_its_sale_mode_or_buyer() {
+        let mut listing = open_listing(&mut marketplace(), &terms(1_000));
+        listing.is_auction = true;
+        listing.highest_bid = 1_500;
+        listing.highest_bidder = Pubkey::new_unique();
+
+        for update in [
+            ListingUpdate {
+                is_auction: Some(false),
+                ..no_update()
+            },
+            ListingUpdate {
+                reserved_buyer: Some(Pubkey::new_unique()),
+                ..no_update()
+            },
+        ] {
+            assert_eq!(
+                listing.apply(&update, NOW).unwrap_err(),
+                MarketplaceError::AuctionHasBids.into()
+            );
+        }
+        listing
+            .apply(
+                &ListingUpdate {
+                    is_auction: Some(true),
+                    ..no_update()
+                },
+                NOW,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn an_update_can_only_extend_the_expiration() {
+        let mut listing = open_listing(&mut marketplace(), &terms(1_000));
+        let expiration_time = listing.expiration_time;
+        for rejected in [expiration_time, expiration_time - 1] {
+            let update = ListingUpdate {
+                expiration_time: Some(rejected),
+                ..no_update()
+            };
+            assert_eq!(
+                listing.apply(&update, NOW).unwrap_err(),
+                MarketplaceError::InvalidExpiration.into()
+            );
+        }
+        let update = ListingUpdate {
+            price: Some(0),
+            ..no_update()
+        };
+        assert_eq!(
+            listing.apply(&update, NOW).unwrap_err(),
+            MarketplaceError::PriceMustBePositive.into()
+        );
+
+        listing
+            .apply(
+                &ListingUpdate {
+                    price: Some(2_000),
+                    expiration_time: Some(expiration_time + DAY),
+                    ..no_update()
+                },
+                NOW,
+            )
+            .unwrap();
+        assert_eq!(listing.price, 2_000);
+        assert_eq!(listing.expiration_time, expiration_time + DAY);
+    }
+
+    fn settlement(amount: u64) -> Settlement {
+        Settlement {
+            buyer: Pubkey::new_unique(),
+            seller: Pubkey::new_unique(),
+            nft_mint: Pubkey::new_unique(),
+            rent_payer: Pubkey::new_unique(),
+            amount,
+            release_at: NOW + 3 * DAY,
+            disputed: false,
+            split: false,
+            bump: 253,
+        }
+    }
+
+    #[test]
+    fn held_proceeds_release_only_after_the_hold_period() {
+        let settlement = settlement(1_000);
+        assert_eq!(
+            settlement.require_releasable(NOW).unwrap_err(),
+            MarketplaceError::SettlementLocked.into()
+        );
+        assert_eq!(
+            settlement
+                .require_releasable(settlement.release_at - 1)
+                .unwrap_err(),
+            MarketplaceError::SettlementLocked.into()
+        );
+        settlement
+            .require_releasable(settlement.release_at)
+            .unwrap();
+        assert_eq!(
+            settlement.released_after_refund(0).unwrap_err(),
+            MarketplaceError::SettlementNotDisputed.into()
+        );
+    }
+
+    #[test]
+    fn a_dispute_must_open_inside_the_window_and_blocks_release() {
+        let mut late = settlement(1_000);
+        assert_eq!(
+            late.open_dispute(late.release_at).unwrap_err(),
+            MarketplaceError::DisputeWindowClosed.into()
+        );
+        assert!(!late.disputed);
+
+        let mut settlement = settlement(1_000);
+        settlement.open_dispute(NOW).unwrap();
+        assert!(settlement.disputed);
+        assert_eq!(
+            settlement.open_dispute(NOW).unwrap_err(),
+            MarketplaceError::SettlementDisputed.into()
+        );
+        assert_eq!(
+            settlement
+                .require_releasable(settlement.release_at + DAY)
+                .unwrap_err(),
+            MarketplaceError::SettlementDisputed.into()
+        );
+    }
+
+    #[test]
+    fn a_resolution_refunds_at_most_the_held_proceeds() {
+        let mut settlement = settlement(1_000);
+        settlement.open_dispute(NOW).unwrap();
+        assert_eq!(settlement.released_after_refund(0).unwrap(), 1_000);
+        assert_eq!(settlement.released_after_refund(400).unwrap(), 600);
+        assert_eq!(settlement.released_after_refund(1_000).unwrap(), 0);
+        assert_eq!(
+            settlement.released_after_refund(1_001).unwrap_err(),
+            MarketplaceError::InvalidRefundAmount.into()
+        );
+    }
+
+    #[test]
+    fn a_consignment_commission_comes_out_of_the_sellers_share() {
+        let agent = Pubkey::new_unique();
+        let mut payout = SalePayout::new(100_000, 250, None, vec![]).unwrap();
+        payout.add_commission(agent, 100_000, 1_000).unwrap();
+        assert_eq!(payout.marketplace_fee, 2_500);
+        assert_eq!(payout.commission, Some((agent, 10_000)));
+        assert_eq!(payout.seller_amount, 87_500);
+
+        let mut payout = SalePayout::new(100_000, 250, None, vec![]).unwrap();
+        assert_eq!(
+            payout.add_commission(agent, 100_000, 9_800).unwrap_err(),
+            MarketplaceError::InvalidCommission.into()
+        );
+    }
+
+    fn revenue_split(bps: &[u16]) -> RevenueSplit {
+        RevenueSplit {
+            nft_mint: Pubkey::new_unique(),
+            authority: Pubkey::new_unique(),
+            recipients: bps.iter().map(|bps| fee_share(*bps)).collect(),
+            primary_sale_done: false,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn a_revenue_split_gives_its_rounding_dust_to_the_first_recipient() {
+        let split = revenue_split(&[3_333, 3_333, 3_334]);
+        let shares = split.shares_of(1_000).unwrap();
+        let amounts: Vec<u64> = shares.iter().map(|(_, amount)| *amount).collect();
+        assert_eq!(amounts, vec![334, 333, 333]);
+        assert_eq!(shares[0].0, split.recipients[0].recipient);
+
+        let mut payout = SalePayout::new(1_000, 0, None, vec![]).unwrap();
+        payout.split_proceeds(&split).unwrap();
+        assert_eq!(payout.seller_amount, 0);
+        assert_eq!(
+            payout.split.iter().map(|(_, amount)| amount).sum::<u64>(),
+            1_000
+        );
+    }
+
+    #[test]
+    fn revenue_split_recipients_must_be_set_and_total_the_whole_sale() {
+        assert!(
+            RevenueSplit::validate_recipients(&revenue_split(&[5_000, 5_000]).recipients).is_ok()
+        );
+
+        let mut unset = revenue_split(&[5_000, 5_000]).recipients;
+        unset[1].recipient = Pubkey::default();
+        let invalid = [
+            vec![],
+            revenue_split(&[5_000, 4_999]).recipients,
+            revenue_split(&[1_250; 9]).recipients,
+            unset,
+        ];
+        for recipients in invalid {
+            assert_eq!(
+                RevenueSplit::validate_recipients(&recipients).unwrap_err(),
+                MarketplaceError::InvalidRevenueSplit.into()
+            );
+        }
+    }
+
+    #[test]
+    fn a_scheduled_listing_runs_its_full_duration_from_the_start_time() {
+        let mut marketplace = marketplace();
+        let scheduled = ListingTerms {
+            start_time: Some(NOW + DAY),
+            ..terms(1_000)
+        };
+        let listing = open_listing(&mut marketplace, &scheduled);
+        assert_eq!(listing.expiration_time, NOW + 8 * DAY);
+        assert!(!listing.has_started(NOW));
+        assert_eq!(
+            listing.require_started(NOW + DAY - 1).unwrap_err(),
+            MarketplaceError::ListingNotStarted.into()
+        );
+        listing.require_started(NOW + DAY).unwrap();
+
+        let immediate = open_listing(&mut marketplace, &terms(1_000));
+        assert!(immediate.has_started(NOW));
+        assert_eq!(immediate.expiration_time, NOW + 7 * DAY);
+    }
+
+    #[test]
+    fn a_start_time_must_be_in_the_future() {
+        let mut listing = Listing::from_legacy(legacy_listing());
+        for start_time in [NOW, NOW - 1] {
+            let terms = ListingTerms {
+                start_time: Some(start_time),
+                ..terms(1_000)
+            };
+            assert_eq!(
+                listing
+                    .open(
+                        Pubkey::new_unique(),
+                        Pubkey::new_unique(),
+                        &terms,
+                        true,
+                        254,
+                        NOW
+                    )
+                    .unwrap_err(),
+                MarketplaceError::InvalidStartTime.into()
+            );
+        }
+    }
 }